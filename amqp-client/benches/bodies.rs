@@ -0,0 +1,135 @@
+// cargo bench --features bench --bench bodies
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use amqp_client::bench::{record_deliveries, ContentBody, ContentFrame, ContentHeader, Frame, FrameReader, MessageProperties};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const FRAME_MAX: usize = 128 * 1024;
+const DELIVERIES: usize = 100;
+
+// the read buffer as the reader sees it, body frames are split off it;
+// leaves out the copy `FrameReader` makes of smaller frames, `frame_reader_bodies` includes it
+fn read_buffer(body_len: usize) -> BytesMut {
+  BytesMut::from(&vec![7u8; body_len][..])
+}
+
+fn assemble(mut buf: BytesMut) -> Bytes {
+  let header = ContentHeader::new(60, buf.len() as i64, MessageProperties::default());
+  let mut content = ContentFrame::WithMethod(Frame::Heartbeat).with_content_header(Box::new(header));
+
+  while !buf.is_empty() {
+    let chunk = buf.split_to(FRAME_MAX.min(buf.len())).freeze();
+    content = content.with_body(ContentBody::from_raw_repr(chunk));
+  }
+
+  let (_, _, body) = content.into_parts().unwrap();
+  body
+}
+
+// what bodies went through before: every frame copied out of the buffer, then appended
+fn assemble_copying(mut buf: BytesMut) -> Vec<u8> {
+  let mut body = vec![];
+
+  while !buf.is_empty() {
+    let mut chunk = buf.split_to(FRAME_MAX.min(buf.len())).to_vec();
+    body.append(&mut chunk);
+  }
+
+  body
+}
+
+// the whole path through `FrameReader`, which copies body frames under 64KB out of its buffer:
+// a message kept unacked then holds only its own body rather than the whole read buffer,
+// at the price of one allocation and copy per smaller frame
+async fn read_deliveries(recording: &[u8]) -> usize {
+  let mut reader = FrameReader::new(recording);
+  let mut body_len = 0;
+
+  for _ in 0..DELIVERIES {
+    let mut content = ContentFrame::WithMethod(reader.next_frame().await.unwrap().1);
+    while !content.is_complete() {
+      content = match reader.next_frame().await.unwrap().1 {
+        Frame::ContentHeader(header) => content.with_content_header(header),
+        Frame::ContentBody(body) => content.with_body(body),
+        frame => panic!("unexpected frame {:?}", frame)
+      };
+    }
+    body_len += content.into_parts().unwrap().2.len();
+  }
+
+  body_len
+}
+
+fn allocations_of<T>(f: impl FnOnce() -> T) -> usize {
+  let before = ALLOCATIONS.load(Ordering::Relaxed);
+  black_box(f());
+  ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn body_assembly(c: &mut Criterion) {
+  let mut group = c.benchmark_group("body_assembly");
+
+  for body_len in [16 * 1024, 1024 * 1024] {
+    let buf = read_buffer(body_len);
+    println!(
+      "{} byte body: {} allocations with Bytes, {} copying",
+      body_len,
+      allocations_of(|| assemble(buf.clone())) - allocations_of(|| buf.clone()),
+      allocations_of(|| assemble_copying(buf.clone())) - allocations_of(|| buf.clone())
+    );
+
+    group.throughput(Throughput::Bytes(body_len as u64));
+    group.bench_with_input(BenchmarkId::new("bytes", body_len), &buf, |b, buf| {
+      b.iter(|| assemble(buf.clone()))
+    });
+    group.bench_with_input(BenchmarkId::new("copying", body_len), &buf, |b, buf| {
+      b.iter(|| assemble_copying(buf.clone()))
+    });
+  }
+
+  group.finish();
+}
+
+fn frame_reader_bodies(c: &mut Criterion) {
+  let runtime = Runtime::new().unwrap();
+  let mut group = c.benchmark_group("frame_reader_bodies");
+
+  for body_len in [16 * 1024, 64 * 1024, 1024 * 1024] {
+    let recording = runtime.block_on(record_deliveries(DELIVERIES, body_len)).unwrap();
+    println!(
+      "{} byte body through FrameReader: {} allocations per delivery",
+      body_len,
+      allocations_of(|| runtime.block_on(read_deliveries(&recording))) / DELIVERIES
+    );
+
+    group.throughput(Throughput::Bytes((body_len * DELIVERIES) as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(body_len), &recording, |b, recording| {
+      b.iter(|| runtime.block_on(read_deliveries(recording)))
+    });
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, body_assembly, frame_reader_bodies);
+criterion_main!(benches);
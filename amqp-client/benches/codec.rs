@@ -0,0 +1,79 @@
+// cargo bench --features bench --bench codec
+use std::time::SystemTime;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use amqp_client::{MessageProperties, PropTable, Property};
+use amqp_client::bench::{Decode, Encode};
+
+// roughly what a server's start properties or a message's headers look like
+fn table() -> PropTable {
+  let capabilities: PropTable = [
+    ("publisher_confirms".into(), Property::Bool(true)),
+    ("exchange_exchange_bindings".into(), Property::Bool(true)),
+    ("basic.nack".into(), Property::Bool(true)),
+    ("consumer_cancel_notify".into(), Property::Bool(true)),
+    ("connection.blocked".into(), Property::Bool(true)),
+    ("authentication_failure_close".into(), Property::Bool(true)),
+  ].into_iter().collect();
+
+  [
+    ("product".into(), Property::LongStr("RabbitMQ".into())),
+    ("version".into(), Property::LongStr("3.11.10".into())),
+    ("platform".into(), Property::LongStr("Erlang/OTP 25.3".into())),
+    ("cluster_name".into(), Property::LongStr("rabbit@localhost".into())),
+    ("x-retries".into(), Property::Int(3)),
+    ("x-deadline".into(), Property::Timestamp(1_700_000_000)),
+    ("x-route".into(), Property::Array(vec![Property::LongStr("a".into()), Property::LongStr("b".into())])),
+    ("capabilities".into(), Property::Table(capabilities)),
+  ].into_iter().collect()
+}
+
+fn properties() -> MessageProperties {
+  MessageProperties::builder()
+    .content_type("application/json")
+    .content_encoding("gzip")
+    .correlation_id("6f1d6c5e-5a1b-4c1e-9a55-0a3c4d1e2f3a")
+    .reply_to("amq.rabbitmq.reply-to")
+    .message_id("42")
+    .timestamp(SystemTime::now())
+    .header("x-origin", "bench")
+    .header("x-retries", 3)
+    .build()
+}
+
+fn tables(c: &mut Criterion) {
+  let table = table();
+  let mut encoded = vec![];
+  encoded.write_proptable(table.clone()).unwrap();
+
+  let mut group = c.benchmark_group("table");
+  group.throughput(Throughput::Bytes(encoded.len() as u64));
+  group.bench_function("encode", |b| {
+    b.iter(|| {
+      let mut buf = Vec::with_capacity(encoded.len());
+      buf.write_proptable(black_box(table.clone())).unwrap();
+      buf
+    })
+  });
+  group.bench_function("decode", |b| {
+    b.iter(|| black_box(&encoded[..]).read_proptable().unwrap())
+  });
+  group.finish();
+}
+
+fn message_properties(c: &mut Criterion) {
+  let properties = properties();
+  let encoded: Vec<u8> = properties.clone().try_into().unwrap();
+
+  let mut group = c.benchmark_group("properties");
+  group.throughput(Throughput::Bytes(encoded.len() as u64));
+  group.bench_function("encode", |b| {
+    b.iter(|| Vec::<u8>::try_from(black_box(properties.clone())).unwrap())
+  });
+  group.bench_function("decode", |b| {
+    b.iter(|| MessageProperties::try_from(black_box(&encoded[..])).unwrap())
+  });
+  group.finish();
+}
+
+criterion_group!(benches, tables, message_properties);
+criterion_main!(benches);
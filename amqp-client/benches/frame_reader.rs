@@ -0,0 +1,37 @@
+// cargo bench --features bench --bench frame_reader
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use amqp_client::bench::{record_deliveries, FrameReader};
+
+const DELIVERIES: usize = 1000;
+
+// reads back every frame of a prerecorded stream of deliveries, three frames each
+async fn read_all(recording: &[u8]) -> usize {
+  let mut reader = FrameReader::new(recording);
+  let mut frames = 0;
+
+  while frames < DELIVERIES * 3 {
+    reader.next_frame().await.unwrap();
+    frames += 1;
+  }
+
+  frames
+}
+
+fn next_frame(c: &mut Criterion) {
+  let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  let mut group = c.benchmark_group("next_frame");
+
+  for body_len in [64, 4 * 1024, 64 * 1024] {
+    let recording = runtime.block_on(record_deliveries(DELIVERIES, body_len)).unwrap();
+
+    group.throughput(Throughput::Bytes(recording.len() as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(body_len), &recording, |b, recording| {
+      b.iter(|| runtime.block_on(read_all(recording)))
+    });
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, next_frame);
+criterion_main!(benches);
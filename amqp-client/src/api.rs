@@ -3,4 +3,5 @@ pub (crate) mod channel;
 pub (crate) mod exchange;
 pub (crate) mod queue;
 pub (crate) mod basic;
+pub (crate) mod transaction;
 pub (crate) mod default_channel;
@@ -1,53 +1,316 @@
 use std::collections::HashMap;
-use log::{info};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use bytes::Bytes;
+use tracing::{info, info_span, warn, Instrument, Span};
+use tokio::sync::watch;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use crate::building_blocks::{Command, CommandPayload};
-use crate::protocol::types::{ChannelId, Long, ShortStr, PropTable};
-use crate::{invoke_sync_method, invoke_command_async, Result, unwrap_frame_variant, MessageProperties};
+use crate::building_blocks::{ChannelDispatcher, ChannelMetrics, Command, CommandPayload, ConnectionMetrics, ConsumerHandoff,
+                             DeliveryTracker, OutgoingTx, RpcSlot};
+use crate::protocol::types::{check_shortstr, check_table, ChannelId, Long, ShortStr, PropTable};
+use crate::{invoke_command_async, Result, unwrap_frame_variant, MessageProperties};
+use crate::api::connection::{BlockedState, ConnectionEvent, ConnectionEvents, ConnectionState};
+use crate::api::connection::error::REPLY_SUCCESS;
+use crate::api::connection::blocked::wait_unblocked;
 use crate::api::exchange::{ExchangeDeclareOptsBuilder, ExchangeType};
 use crate::api::queue::QueueDeclareOptsBuilder;
+use crate::api::transaction::Transaction;
+use crate::utils::IdAllocator;
+use crate::protocol::compression::{encode_body, CompressionOpts};
 use crate::protocol::message::{Message};
-use crate::protocol::frame::{FrameEnvelope, Frame, BasicConsume, BasicPublish, ChannelOpen,
-                             ContentBody, ContentHeader, ExchangeDeclare, QueueBind, QueueDeclare,
-                             QueueUnbind};
+use crate::protocol::frame::{FrameEnvelope, Frame, BasicConsume, BasicPublish, ChannelClose, ChannelCloseOk, ChannelFlow,
+                             ChannelFlowOk, ChannelOpen,
+                             ContentHeader, ExchangeDeclare, OutgoingFrame, QueueBind,
+                             QueueDeclare, QueueUnbind, TxCommit, TxRollback, TxSelect, BasicRecover,
+                             BasicRecoverAsync};
 
+pub mod error;
+pub mod state;
+pub use self::error::ChannelError;
+pub use self::state::ChannelState;
+
+// what a consumer receives; when the channel or its connection fails the last item is the reason, then the receiver ends
+pub type Delivery = std::result::Result<Message, ChannelError>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct ChannelOpts {
+  pub blocked_timeout: Option<Duration>,
+  pub flow_timeout: Option<Duration>,
+  pub compression: Option<CompressionOpts>,
+  pub max_decompressed_size: usize,
+  pub events: ConnectionEvents,
+  pub connection_span: Span,
+  pub metrics: ConnectionMetrics,
+}
+
+// a cheap handle, clones talk to the same channel and the close applies to all of them
+#[derive(Clone)]
 pub struct AmqChannel {
   pub id: ChannelId,
-  outgoing_tx: UnboundedSender<FrameEnvelope>,
-  command_tx: UnboundedSender<Command>
+  outgoing_tx: OutgoingTx,
+  command_tx: UnboundedSender<Command>,
+  blocked_rx: watch::Receiver<BlockedState>,
+  flow_rx: watch::Receiver<bool>,
+  tracker: Arc<DeliveryTracker>,
+  rpc: Arc<RpcSlot>,
+  consumer_handoff: ConsumerHandoff,
+  // numbers the consumer tags this channel hands out
+  consumer_seq: Arc<AtomicU64>,
+  connection_state_rx: watch::Receiver<ConnectionState>,
+  state_tx: Arc<watch::Sender<ChannelState>>,
+  id_allocator: Arc<IdAllocator>,
+  opts: ChannelOpts,
+  tx_selected: Arc<AtomicBool>,
+  span: Span,
+  metrics: ChannelMetrics,
 }
 
 impl AmqChannel {
-  pub async fn open(
+  pub(crate) async fn open(
     id: ChannelId,
-    outgoing_tx: UnboundedSender<FrameEnvelope>,
-    incoming_rx: UnboundedReceiver<FrameEnvelope>,
+    outgoing_tx: OutgoingTx,
     command_tx: UnboundedSender<Command>,
+    blocked_rx: watch::Receiver<BlockedState>,
+    connection_state_rx: watch::Receiver<ConnectionState>,
+    id_allocator: Arc<IdAllocator>,
+    opts: ChannelOpts,
   ) -> Result<Self> {
-    let open_method = ChannelOpen { reserved1: ShortStr("".into()) }.into_frame();
-    let _frame = invoke_sync_method!(id, command_tx, outgoing_tx, open_method).await?;
+    let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+    invoke_command_async!(command_tx, CommandPayload::RegisterChannel((id, incoming_tx)));
+
+    let (flow_tx, flow_rx) = watch::channel(true);
+    let metrics = opts.metrics.channel(id);
     let channel = Self {
       id,
       outgoing_tx,
-      command_tx
+      command_tx,
+      blocked_rx,
+      flow_rx,
+      tracker: Arc::new(DeliveryTracker::new(metrics.clone())),
+      rpc: Arc::new(RpcSlot::new(metrics.clone())),
+      consumer_handoff: Default::default(),
+      consumer_seq: Default::default(),
+      connection_state_rx,
+      state_tx: Arc::new(watch::channel(ChannelState::Open).0),
+      id_allocator,
+      span: info_span!(parent: &opts.connection_span, "channel", channel = id),
+      opts,
+      tx_selected: Arc::new(AtomicBool::new(false)),
+      metrics
     };
 
-    // channel.spawn_incoming_msg_handler(incoming_rx);
+    // the channel task resolves open-ok, so it has to run first
+    channel.spawn_incoming_msg_handler(incoming_rx, flow_tx);
+
+    let open_method = ChannelOpen { reserved1: ShortStr("".into()) }.into_frame();
+    if let Err(err) = channel.rpc.call(id, &channel.outgoing_tx, open_method).await {
+      Self::release(id, &channel.command_tx, &channel.id_allocator).await?;
+      return Err(err);
+    }
+    info!(parent: &channel.span, "channel opened");
 
     Ok(channel)
   }
 
-  // todo: do we need this?
-  fn spawn_incoming_msg_handler(&self, mut incoming_rx: UnboundedReceiver<FrameEnvelope>) {
+  // one task per channel assembles its deliveries, resolves its replies
+  // and handles the methods the server sends on its own
+  fn spawn_incoming_msg_handler(&self, mut incoming_rx: UnboundedReceiver<FrameEnvelope>, flow_tx: watch::Sender<bool>) {
+    let id = self.id;
+    let mut dispatcher = ChannelDispatcher::new(
+      id,
+      self.outgoing_tx.clone(),
+      self.tracker.clone(),
+      self.rpc.clone(),
+      self.consumer_handoff.clone(),
+      self.opts.max_decompressed_size,
+      self.span.clone()
+    );
+    let outgoing_tx = self.outgoing_tx.clone();
+    let command_tx = self.command_tx.clone();
+    let id_allocator = self.id_allocator.clone();
+    let state_tx = self.state_tx.clone();
+    let connection_state_rx = self.connection_state_rx.clone();
+    let events = self.opts.events.clone();
+    let tracker = self.tracker.clone();
+
     tokio::spawn(async move {
       while let Some((_, frame)) = incoming_rx.recv().await {
+        let frame = match dispatcher.dispatch(frame) {
+          Some(frame) => frame,
+          None => continue
+        };
+
         match frame {
-          _ => {
-            todo!("Implement handler")
+          Frame::ChannelFlow(flow) => {
+            info!("channel {} flow changed by server, active: {}", id, flow.active != 0);
+            flow_tx.send_replace(flow.active != 0);
+
+            if outgoing_tx.send((id, ChannelFlowOk { active: flow.active }.into_frame()).into()).is_err() {
+              break;
+            }
+          },
+          Frame::ChannelClose(close) => {
+            warn!("channel {} closed by server, code: {}, reason: {}", id, close.reply_code, close.reply_text.0);
+            let reply_code = close.reply_code;
+            let reply_text = close.reply_text.0;
+            state_tx.send_replace(ChannelState::Closed(Some(ChannelError::Closed {
+              id,
+              reply_code,
+              reply_text: reply_text.clone()
+            })));
+            let _ = outgoing_tx.send((id, ChannelCloseOk {}.into_frame()).into());
+            // messages of this channel must not ack on whichever channel gets the id next
+            tracker.reset();
+            let _ = Self::release(id, &command_tx, &id_allocator).await;
+            events.emit(ConnectionEvent::ChannelClosed { id, reply_code, reply_text });
+            break;
+          },
+          frame => {
+            warn!("unhandled frame on channel {}: {:?}", id, frame);
           }
         }
       }
+
+      // the queue is also dropped when the connection goes away, the failure is kept as the reason
+      let reason = match &*connection_state_rx.borrow() {
+        ConnectionState::Closed(Some(err)) => Some(ChannelError::ConnectionFailed(id, err.clone())),
+        _ => None
+      };
+      state_tx.send_if_modified(|state| {
+        let closing = !state.is_closed();
+        if closing {
+          *state = ChannelState::Closed(reason);
+        }
+        closing
+      });
+      if let ChannelState::Closed(Some(err)) = &*state_tx.borrow() {
+        dispatcher.fail(err);
+      }
+      info!("exited channel {} loop", id);
+    }.instrument(self.span.clone()));
+  }
+
+  pub fn is_open(&self) -> bool {
+    self.state_tx.borrow().is_open() && self.connection_state_rx.borrow().is_open()
+  }
+
+  pub fn state(&self) -> ChannelState {
+    self.state_tx.borrow().clone()
+  }
+
+  pub fn state_changes(&self) -> watch::Receiver<ChannelState> {
+    self.state_tx.subscribe()
+  }
+
+  fn ensure_open(&self) -> Result<()> {
+    if self.is_open() {
+      Ok(())
+    } else {
+      Err(self.closed_error())
+    }
+  }
+
+  // prefers the reason the channel or its connection failed with over a plain `NotOpen`
+  fn closed_error(&self) -> anyhow::Error {
+    if let ConnectionState::Closed(Some(err)) = &*self.connection_state_rx.borrow() {
+      return ChannelError::ConnectionFailed(self.id, err.clone()).into();
+    }
+
+    match &*self.state_tx.borrow() {
+      ChannelState::Closed(Some(err)) => err.clone().into(),
+      _ => ChannelError::NotOpen(self.id).into()
+    }
+  }
+
+  // closes the channel for every clone of the handle, a no-op when it is already closed
+  pub async fn close(&self) -> Result<()> {
+    let mut closing = false;
+    self.state_tx.send_if_modified(|state| {
+      closing = state.is_open();
+      if closing {
+        *state = ChannelState::Closing;
+      }
+      closing
     });
+
+    if !closing {
+      return Ok(());
+    }
+
+    info!(parent: &self.span, "closing channel {}", self.id);
+    let method = ChannelClose {
+      reply_code: REPLY_SUCCESS,
+      reply_text: "Closed".into(),
+      class_id: 0,
+      method_id: 0,
+    };
+    let closed = async {
+      let frame = self.rpc.call(self.id, &self.outgoing_tx, method.into_frame()).await?;
+      let _close_ok = unwrap_frame_variant!(frame, ChannelCloseOk);
+      // messages of this channel must not ack on whichever channel gets the id next
+      self.tracker.reset();
+      Self::release(self.id, &self.command_tx, &self.id_allocator).await
+    }.await;
+
+    self.state_tx.send_replace(ChannelState::Closed(None));
+    self.opts.events.emit(ConnectionEvent::ChannelClosed {
+      id: self.id,
+      reply_code: REPLY_SUCCESS,
+      reply_text: "Closed".into()
+    });
+    closed
+  }
+
+  // the id is reusable only once the connection stopped routing frames to this channel
+  async fn release(id: ChannelId, command_tx: &UnboundedSender<Command>, id_allocator: &IdAllocator) -> Result<()> {
+    invoke_command_async!(command_tx, CommandPayload::UnregisterChannel(id));
+    id_allocator.release(id);
+
+    Ok(())
+  }
+
+  // false while the server has paused publishing on this channel
+  pub fn is_flow_active(&self) -> bool {
+    *self.flow_rx.borrow()
+  }
+
+  pub fn flow_state(&self) -> watch::Receiver<bool> {
+    self.flow_rx.clone()
+  }
+
+  // asks the server to pause (false) or resume (true) deliveries to this channel's consumers
+  pub async fn flow(&self, active: bool) -> Result<()> {
+    info!("requesting channel {} flow, active: {}", self.id, active);
+    let method = ChannelFlow { active: active as u8 };
+    let frame = self.invoke_sync_method(method.into_frame()).await?;
+    let _flow_ok = unwrap_frame_variant!(frame, ChannelFlowOk);
+
+    Ok(())
+  }
+
+  async fn wait_flow_active(&self) -> Result<()> {
+    if self.is_flow_active() {
+      return Ok(());
+    }
+
+    let timeout = match self.opts.flow_timeout {
+      Some(timeout) => timeout,
+      None => return Err(ChannelError::Paused(self.id).into())
+    };
+
+    let mut flow_rx = self.flow_rx.clone();
+    let resumed = tokio::time::timeout(timeout, async {
+      while !*flow_rx.borrow_and_update() {
+        flow_rx.changed().await?;
+      }
+      Ok::<(), watch::error::RecvError>(())
+    }).await;
+
+    match resumed {
+      Ok(Ok(_)) => Ok(()),
+      _ => Err(ChannelError::Paused(self.id).into())
+    }
   }
 
   pub async fn declare_exchange(
@@ -78,8 +341,11 @@ impl AmqChannel {
     info!("declare exchange");
     let mut builder = ExchangeDeclareOptsBuilder::new();
     configure(&mut builder);
-    let method = ExchangeDeclare::from(builder.build());
-    let _frame = invoke_sync_method!(self.id, self.command_tx, self.outgoing_tx, method.into_frame()).await?;
+    let opts = builder.build();
+    check_shortstr("exchange name", &opts.name)?;
+    check_table("exchange arguments", &opts.props)?;
+    let method = ExchangeDeclare::from(opts);
+    let _frame = self.invoke_sync_method(method.into_frame()).await?;
     info!("declared exchange");
 
     Ok(())
@@ -105,7 +371,9 @@ impl AmqChannel {
     }).await
   }
   async fn invoke_sync_method(&self, frame: Frame) -> Result<Frame> {
-    Ok(invoke_sync_method!(self.id, self.command_tx, self.outgoing_tx, frame).await?)
+    self.ensure_open()?;
+    // the call fails only once the channel or the whole connection went away
+    self.rpc.call(self.id, &self.outgoing_tx, frame).await.map_err(|_| self.closed_error())
   }
 
   pub async fn declare_queue_with_builder<F>(&self, configure: F) -> Result<String>
@@ -116,7 +384,10 @@ impl AmqChannel {
 
     configure(&mut opts);
 
-    let method = QueueDeclare::from(opts.build());
+    let opts = opts.build();
+    check_shortstr("queue name", &opts.name)?;
+    check_table("queue arguments", &opts.props)?;
+    let method = QueueDeclare::from(opts);
     let frame = self.invoke_sync_method(method.into_frame()).await?;
     let declare_ok = unwrap_frame_variant!(frame, QueueDeclareOk);
     info!("declared queue {}", &declare_ok.name.0);
@@ -126,6 +397,9 @@ impl AmqChannel {
 
   pub async fn bind(&self, queue_name: &str, exchange_name: &str, routing_key: &str) -> Result<()> {
     info!("bind queue: {} to: exchange {} with key: {}", queue_name.clone(), exchange_name.clone(), routing_key.clone());
+    check_shortstr("queue name", queue_name)?;
+    check_shortstr("exchange name", exchange_name)?;
+    check_shortstr("routing key", routing_key)?;
     let method = QueueBind {
       reserved1: 0,
       queue: queue_name.into(),
@@ -143,6 +417,9 @@ impl AmqChannel {
   }
 
   pub async fn unbind(&self, queue: &str, exchange: &str, routing_key: &str) -> Result<()> {
+    check_shortstr("queue name", queue)?;
+    check_shortstr("exchange name", exchange)?;
+    check_shortstr("routing key", routing_key)?;
     let method = QueueUnbind {
       reserved1: 0,
       queue: queue.into(),
@@ -158,27 +435,78 @@ impl AmqChannel {
     Ok(())
   }
 
-  pub async fn consume(&self, queue: &str) -> Result<UnboundedReceiver<Message>> {
+  pub async fn consume(&self, queue: &str) -> Result<UnboundedReceiver<Delivery>> {
     info!("consuming queue: {}", queue.clone());
+    // the tag is chosen here rather than by the server, so the handoff entry is known before the reply
+    check_shortstr("queue name", queue)?;
+    let tag = format!("ctag-{}.{}", self.id, self.consumer_seq.fetch_add(1, Ordering::Relaxed) + 1);
     let method = BasicConsume {
       reserved1: 0,
       queue: queue.into(),
-      tag: "".into(),
+      tag: tag.as_str().into(),
       flags: 0,
       props: HashMap::new()
     };
 
-    let frame = self.invoke_sync_method(method.into_frame()).await?;
-    let consume_ok = unwrap_frame_variant!(frame, BasicConsumeOk);
-
+    // handed to the channel task before the call, deliveries may follow consume-ok right away
     let (consumer_tx, consumer_rx) = mpsc::unbounded_channel();
-    invoke_command_async!(self.command_tx, CommandPayload::RegisterConsumer(self.id, consume_ok.tag.0.clone(), consumer_tx));
+    self.consumer_handoff.lock().unwrap().insert(tag.clone(), (queue.into(), consumer_tx));
+
+    let frame = self.invoke_sync_method(method.into_frame()).await;
+    let consume_ok = match frame {
+      Ok(frame) => unwrap_frame_variant!(frame, BasicConsumeOk),
+      Err(err) => {
+        self.consumer_handoff.lock().unwrap().remove(&tag);
+        return Err(err);
+      }
+    };
     info!("consume ok with tag: {}", consume_ok.tag.0);
 
     Ok(consumer_rx)
   }
 
-  pub async fn publish(&self, exchange: &str, routing_key: &str, body: Vec<u8>, properties: MessageProperties) -> Result<()> {
+  pub async fn publish(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    body: impl Into<Bytes>,
+    properties: MessageProperties
+  ) -> Result<()> {
+    // field names follow the OpenTelemetry messaging conventions
+    let span = info_span!(
+      parent: &self.span,
+      "publish",
+      otel.kind = "producer",
+      otel.name = %format_args!("{} publish", exchange),
+      messaging.system = "rabbitmq",
+      messaging.operation = "publish",
+      messaging.destination.name = exchange,
+      messaging.rabbitmq.destination.routing_key = routing_key,
+      messaging.message.id = properties.message_id.as_deref(),
+      messaging.message.conversation_id = properties.correlation_id.as_deref()
+    );
+
+    self.publish_message(exchange, routing_key, body.into(), properties).instrument(span).await
+  }
+
+  async fn publish_message(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    body: Bytes,
+    mut properties: MessageProperties
+  ) -> Result<()> {
+    check_shortstr("exchange name", exchange)?;
+    check_shortstr("routing key", routing_key)?;
+    self.ensure_open()?;
+    #[cfg(feature = "opentelemetry")]
+    crate::protocol::propagation::inject(&Span::current(), &mut properties);
+    let body = encode_body(self.opts.compression.as_ref(), &mut properties, body)?;
+    properties.validate()?;
+    if let Some(timeout) = self.opts.blocked_timeout {
+      wait_unblocked(&self.blocked_rx, timeout).await?;
+    }
+    self.wait_flow_active().await?;
 
     info!("Publishing message");
     let method = BasicPublish {
@@ -187,64 +515,113 @@ impl AmqChannel {
       routing_key: routing_key.into(),
       flags: 0,
     };
-    let header = ContentHeader {
-      class_id: 60,
-      body_len: body.len() as Long,
-      prop_list: properties,
-    };
-    let body = ContentBody(body);
-    self.outgoing_tx.send((self.id, method.into_frame())).unwrap();
-    self.outgoing_tx.send((self.id, header.into_frame())).unwrap();
-    self.outgoing_tx.send((self.id, body.into_frame())).unwrap();
-
+    let body_len = body.len();
+    let header = ContentHeader::new(60, body_len as Long, properties);
+    // waits here while the writer is behind
+    self.outgoing_tx.send_content(OutgoingFrame::Content(self.id, method.into_frame(), Box::new(header), body)).await?;
+    self.metrics.published(body_len);
 
     info!("Message was published");
 
     Ok(())
   }
 
-//   pub async fn flow(&self, active: bool) -> Result<()> {
-//     use self::methods::Flow;
-//
-//     debug!("Invoking channel {} Flow", self.id);
-//     if self.active == active {
-//       return Ok(())
-//     }
-//
-//     self.invoke_sync_method(Flow { active: active as u8 }).await?;
-//     // self.active = active;
-//
-//     Ok(())
-//   }
-//
-
-
-//
-//   pub async fn close(&self) -> Result<()> {
-//     use crate::channel::methods::Close;
-//
-//     debug!("Closing channel {}", self.id);
-//     self.invoke_sync_method(Close {
-//       reply_code: 200,
-//       reply_text: "Closed".to_string(),
-//       class_id: 0,
-//       method_id: 0,
-//     }).await?;
-//
-//     Ok(())
-//   }
-//
-//   async fn invoke_sync_method<T: AmqpMethodArgs>(&self, args: T) -> Result<RawFrame> {
-//     let (tx, rx) = oneshot::channel::<RawFrame>();
-//     let sync_waiter_queue = self.sync_waiter_queue.clone();
-//     sync_waiter_queue.lock().unwrap().push(tx);
-//
-//     let request: Frame2<T> = Frame2::new(self.id, args);
-//     {
-//       let mut writer = self.con_writer.lock().await;
-//       writer.send_raw_frame(request.into()).await?;
-//     }
-//
-//     Ok(rx.await?)
-//   }
+  // redelivers (requeue) or resends to the same consumer every unacknowledged message,
+  // messages received before recover-ok can't be acked or rejected afterwards
+  pub async fn recover(&self, requeue: bool) -> Result<()> {
+    info!("recovering channel {}, requeue: {}", self.id, requeue);
+    // the channel task resets the tracker when recover-ok arrives
+    let frame = self.invoke_sync_method(BasicRecover { requeue }.into_frame()).await?;
+    let _recover_ok = unwrap_frame_variant!(frame, BasicRecoverOk);
+    self.opts.events.emit(ConnectionEvent::Recovered { id: self.id, requeue });
+
+    Ok(())
+  }
+
+  // deprecated by the spec in favour of `recover`, RabbitMQ doesn't implement it
+  pub fn recover_async(&self, requeue: bool) -> Result<()> {
+    self.ensure_open()?;
+    self.tracker.reset();
+    self.outgoing_tx.send((self.id, BasicRecoverAsync { requeue }.into_frame()).into())?;
+
+    Ok(())
+  }
+
+  pub fn unsettled_count(&self) -> usize {
+    self.tracker.unsettled_count()
+  }
+
+  pub async fn tx_select(&self) -> Result<()> {
+    info!("selecting transactions on channel {}", self.id);
+    let frame = self.invoke_sync_method(TxSelect {}.into_frame()).await?;
+    let _select_ok = unwrap_frame_variant!(frame, TxSelectOk);
+    self.tx_selected.store(true, Ordering::Release);
+
+    Ok(())
+  }
+
+  pub async fn tx_commit(&self) -> Result<()> {
+    let frame = self.invoke_sync_method(TxCommit {}.into_frame()).await?;
+    let _commit_ok = unwrap_frame_variant!(frame, TxCommitOk);
+    info!("transaction committed on channel {}", self.id);
+
+    Ok(())
+  }
+
+  pub async fn tx_rollback(&self) -> Result<()> {
+    let frame = self.invoke_sync_method(TxRollback {}.into_frame()).await?;
+    let _rollback_ok = unwrap_frame_variant!(frame, TxRollbackOk);
+    info!("transaction rolled back on channel {}", self.id);
+
+    Ok(())
+  }
+
+  // the returned guard rolls back unless it is committed, e.g. when an error bubbles up with `?`
+  pub async fn transaction(&self) -> Result<Transaction<'_>> {
+    if !self.tx_selected.load(Ordering::Acquire) {
+      self.tx_select().await?;
+    }
+
+    Ok(Transaction::new(self))
+  }
+
+  // used where the rollback can't be awaited, e.g. when a transaction guard is dropped;
+  // queued right away, so acks, publishes and methods that follow reach the broker after it
+  pub(crate) fn queue_tx_rollback(&self) {
+    if !self.is_open() {
+      return;
+    }
+
+    if let Err(err) = self.rpc.call_detached(self.id, &self.outgoing_tx, TxRollback {}.into_frame()) {
+      warn!(parent: &self.span, "failed to roll back transaction on channel {}: {}", self.id, err);
+    }
+  }
+
+  #[cfg(feature = "serde")]
+  pub async fn publish_json<T: serde::Serialize + ?Sized>(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    value: &T,
+    mut properties: MessageProperties
+  ) -> Result<()> {
+    use crate::protocol::serialization::JSON_CONTENT_TYPE;
+
+    properties.content_type = Some(JSON_CONTENT_TYPE.into());
+    self.publish(exchange, routing_key, serde_json::to_vec(value)?, properties).await
+  }
+
+  #[cfg(feature = "msgpack")]
+  pub async fn publish_msgpack<T: serde::Serialize + ?Sized>(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    value: &T,
+    mut properties: MessageProperties
+  ) -> Result<()> {
+    use crate::protocol::serialization::MSGPACK_CONTENT_TYPE;
+
+    properties.content_type = Some(MSGPACK_CONTENT_TYPE.into());
+    self.publish(exchange, routing_key, rmp_serde::to_vec_named(value)?, properties).await
+  }
 }
@@ -0,0 +1,35 @@
+use std::fmt::{Display, Formatter};
+use crate::api::connection::ConnectionError;
+use crate::protocol::types::{ChannelId, Short};
+
+#[derive(Debug, Clone)]
+pub enum ChannelError {
+  // the server asked the channel to stop publishing with channel.flow
+  Paused(ChannelId),
+  // the channel, or the connection it belongs to, is closed or closing
+  NotOpen(ChannelId),
+  Closed { id: ChannelId, reply_code: Short, reply_text: String },
+  // the connection failed underneath the channel, e.g. with a heartbeat timeout
+  ConnectionFailed(ChannelId, ConnectionError),
+}
+
+impl Display for ChannelError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ChannelError::Paused(id) => {
+        write!(f, "Channel {} is paused by the server", id)
+      },
+      ChannelError::NotOpen(id) => {
+        write!(f, "Channel {} is not open", id)
+      },
+      ChannelError::Closed { id, reply_code, reply_text } => {
+        write!(f, "Channel {} closed with code: {}, reason: {}", id, reply_code, reply_text)
+      },
+      ChannelError::ConnectionFailed(id, err) => {
+        write!(f, "Channel {} lost its connection: {}", id, err)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ChannelError {}
@@ -0,0 +1,20 @@
+use crate::api::channel::error::ChannelError;
+
+#[derive(Debug, Clone, Default)]
+pub enum ChannelState {
+  #[default]
+  Open,
+  Closing,
+  // carries the reason when the server closed the channel
+  Closed(Option<ChannelError>),
+}
+
+impl ChannelState {
+  pub fn is_open(&self) -> bool {
+    matches!(self, ChannelState::Open)
+  }
+
+  pub fn is_closed(&self) -> bool {
+    matches!(self, ChannelState::Closed(_))
+  }
+}
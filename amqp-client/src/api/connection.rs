@@ -1,120 +1,390 @@
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::Duration;
 
-use log::{info};
-use tokio::io::{BufReader, BufWriter};
+use anyhow::bail;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time::Instant;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use crate::protocol::types::{ChannelId, LongStr, Property, ShortStr, PropTable};
-use crate::protocol::frame::{Frame, FrameEnvelope, ConnectionOpen, ConnectionStartOk, ConnectionTuneOk, ContentFrame, ConnectionClose};
+use crate::protocol::types::{check_shortstr, Int, Property, ShortStr, Short, PropTable};
+use crate::protocol::frame::{Frame, ConnectionOpen, ConnectionSecureOk, ConnectionStartOk, ConnectionTuneOk,
+                             ConnectionClose, ConnectionUpdateSecret, OutgoingFrame};
 
-use crate::{invoke_command_async, invoke_sync_method, Result, unwrap_frame_variant};
-use crate::api::channel::AmqChannel;
+use crate::{Result, unwrap_frame_variant};
+use crate::api::channel::{AmqChannel, ChannelOpts};
 use crate::api::connection::options::ConnectionArgs;
 use crate::api::connection::constants::PROTOCOL_HEADER;
 use crate::api::default_channel::DefaultAmqChannel;
-use crate::building_blocks::{ChannelManager, Command, CommandPayload};
-use self::constants::{COPYRIGHT, DEFAULT_AUTH_MECHANISM, DEFAULT_LOCALE, INFORMATION, PLATFORM, PRODUCT};
-use crate::protocol::net::{FrameReader, FrameWriter};
+use crate::building_blocks::{outgoing_queue, ChannelManager, Command, CommandPayload, ConnectionMetrics, OutgoingMetrics,
+                             OutgoingRx, OutgoingTx, RpcSlot};
+use self::constants::{COPYRIGHT, DEFAULT_LOCALE, INFORMATION, PLATFORM, PRODUCT};
+use crate::protocol::net::{FrameReader, FrameWriter, TransportReader, TransportWriter};
 use crate::utils::IdAllocator;
 
+pub mod blocked;
+pub mod capabilities;
 pub mod constants;
+pub mod credentials;
+pub mod error;
+pub mod events;
 pub mod factory;
 pub mod options;
+pub mod sasl;
+pub mod state;
+pub use self::blocked::BlockedState;
+pub use self::capabilities::{Capabilities, ServerInfo};
+pub use self::error::ConnectionError;
+pub use self::events::{ConnectionEvent, ConnectionEvents};
+pub use self::state::ConnectionState;
+use self::error::{ACCESS_REFUSED, REPLY_SUCCESS};
+use self::sasl::SaslMechanism;
+use self::credentials::CredentialsProvider;
 pub use self::factory::ConnectionFactory;
 
+const SECRET_REFRESH_RATIO: f64 = 0.8;
+const SECRET_RETRY_DELAY: Duration = Duration::from_secs(5);
+// how long close waits for close-ok before dropping the connection anyway
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+// bytes the writer collects from the outgoing queue before flushing them together
+const MAX_WRITE_BATCH: usize = 256 * 1024;
+
+// a cheap handle, clones share the same socket and the close applies to all of them
+#[derive(Clone)]
 pub struct Connection {
-  arguments: ConnectionArgs,
-  id_allocator: IdAllocator,
-  message_tx: UnboundedSender<FrameEnvelope>,
+  arguments: Arc<ConnectionArgs>,
+  server: Arc<ServerInfo>,
+  id_allocator: Arc<IdAllocator>,
+  message_tx: OutgoingTx,
+  // synchronous methods on channel 0
+  rpc: Arc<RpcSlot>,
   command_tx: UnboundedSender<Command>,
   close_tx: broadcast::Sender<()>,
+  blocked_tx: Arc<watch::Sender<BlockedState>>,
+  state_tx: Arc<watch::Sender<ConnectionState>>,
+  // parent of the channel spans, the connection's own tasks run in it
+  span: Span,
+  metrics: ConnectionMetrics,
 }
 
 impl Connection {
   pub async fn open(stream: TcpStream, args: ConnectionArgs) -> Result<Connection> {
-    let stream_parts = stream.into_split();
-    let mut reader = FrameReader::new(BufReader::new(stream_parts.0));
-    let mut writer = FrameWriter::new(BufWriter::new(stream_parts.1));
+    let (reader, writer) = stream.into_split();
+    Self::open_split(Box::new(reader), Box::new(writer), args).await
+  }
 
-    let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+  // runs the protocol over an already connected stream, e.g. TLS or an in-memory duplex
+  pub async fn open_transport<S>(stream: S, args: ConnectionArgs) -> Result<Connection>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+  {
+    let (reader, writer) = tokio::io::split(stream);
+    Self::open_split(Box::new(reader), Box::new(writer), args).await
+  }
+
+  async fn open_split(
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    args: ConnectionArgs
+  ) -> Result<Connection> {
+    let metrics = ConnectionMetrics::new(args.display_name());
+    let mut reader = FrameReader::new(reader);
+    reader.set_metrics(metrics.clone());
+    let mut writer = FrameWriter::new(writer);
+    writer.set_metrics(metrics.clone());
+
+    let (msg_tx, msg_rx) = outgoing_queue(args.outgoing_queue_capacity);
     let (command_tx, command_rx) = mpsc::unbounded_channel();
     let (close_tx, close_rx) = broadcast::channel::<()>(1);
-
-    let connection = Self {
-      arguments: args,
-      id_allocator: IdAllocator::new(),
+    let span = info_span!(
+      "connection",
+      host = %args.address.host,
+      port = args.address.port,
+      vhost = %args.address.vhost,
+      login = %args.address.login
+    );
+
+    let mut connection = Self {
+      id_allocator: Arc::new(IdAllocator::new(args.max_channels)),
+      arguments: Arc::new(args),
+      server: Default::default(),
       message_tx: msg_tx,
+      rpc: Arc::new(RpcSlot::new(metrics.channel(0))),
       command_tx,
-      close_tx
+      close_tx,
+      blocked_tx: Arc::new(watch::channel(BlockedState::Unblocked).0),
+      state_tx: Arc::new(watch::channel(ConnectionState::Open).0),
+      span: span.clone(),
+      metrics
     };
 
-    connection.handshake(&mut reader, &mut writer).await?;
+    connection.handshake(&mut reader, &mut writer).instrument(span.clone()).await?;
     connection.spawn_connection_handlers(reader, writer, msg_rx, command_rx);
+    info!(parent: &span, "connection opened");
+    connection.events().emit(ConnectionEvent::Opened);
 
     Ok(connection)
   }
 
-  pub async fn create_channel(&mut self) -> Result<AmqChannel> {
-    let id = self.id_allocator.allocate();
-    info!("create channel");
+  pub fn server_properties(&self) -> &PropTable {
+    &self.server.properties
+  }
 
-    let (channel_tx, channel_rx) = mpsc::unbounded_channel();
+  pub fn server_info(&self) -> &ServerInfo {
+    &self.server
+  }
 
-    invoke_command_async!(self.command_tx, CommandPayload::RegisterChannel((id, channel_tx)));
+  pub fn capabilities(&self) -> &Capabilities {
+    &self.server.capabilities
+  }
 
-    let channel = AmqChannel::open(id, self.message_tx.clone(), channel_rx, self.command_tx.clone()).await?;
+  pub fn is_blocked(&self) -> bool {
+    self.blocked_tx.borrow().is_blocked()
+  }
+
+  // resolves `changed()` every time the broker blocks or unblocks the connection
+  pub fn blocked_state(&self) -> watch::Receiver<BlockedState> {
+    self.blocked_tx.subscribe()
+  }
+
+  // lifecycle hooks and event stream, shared with `ConnectionArgs::events`
+  pub fn events(&self) -> &ConnectionEvents {
+    &self.arguments.events
+  }
+
+  // what publishers have queued for the socket and how much room is left
+  pub fn outgoing_metrics(&self) -> OutgoingMetrics {
+    self.message_tx.metrics()
+  }
+
+  pub fn is_open(&self) -> bool {
+    self.state_tx.borrow().is_open()
+  }
+
+  pub fn state(&self) -> ConnectionState {
+    self.state_tx.borrow().clone()
+  }
+
+  // resolves `changed()` on every lifecycle transition, e.g. to wait for the connection to close
+  pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+    self.state_tx.subscribe()
+  }
+
+  pub async fn create_channel(&self) -> Result<AmqChannel> {
+    if !self.is_open() {
+      return Err(ConnectionError::Closed { reply_code: 0, reply_text: "Connection is not open".into() }.into());
+    }
+
+    let id = self.id_allocator.allocate()?;
+    info!("create channel {}", id);
+
+    let channel = AmqChannel::open(
+      id,
+      self.message_tx.clone(),
+      self.command_tx.clone(),
+      self.blocked_tx.subscribe(),
+      self.state_tx.subscribe(),
+      self.id_allocator.clone(),
+      ChannelOpts {
+        // a server without connection.blocked never reports being blocked, there is nothing to wait for
+        blocked_timeout: self.arguments.blocked_publish_timeout.filter(|_| self.server.capabilities.connection_blocked()),
+        flow_timeout: self.arguments.flow_publish_timeout,
+        compression: self.arguments.compression,
+        max_decompressed_size: self.arguments.max_decompressed_size,
+        events: self.arguments.events.clone(),
+        connection_span: self.span.clone(),
+        metrics: self.metrics.clone()
+      }
+    ).await?;
 
     info!("channel created");
     Ok(channel)
   }
 
-  pub async fn close(self) -> Result<()> {
-    // todo!("provide reply code and text");
-    let method = ConnectionClose {
-      reply_code: 0,
-      reply_text: "Connection closed".into(),
-      class_id: 0,
-      method_id: 0,
+  pub async fn update_secret(&self, new_secret: &str, reason: &str) -> Result<()> {
+    Self::invoke_update_secret(&self.rpc, &self.message_tx, new_secret, reason).await
+  }
+
+  async fn invoke_update_secret(
+    rpc: &RpcSlot,
+    message_tx: &OutgoingTx,
+    new_secret: &str,
+    reason: &str
+  ) -> Result<()> {
+    info!("updating connection secret");
+    check_shortstr("update-secret reason", reason)?;
+    let method = ConnectionUpdateSecret {
+      new_secret: new_secret.into(),
+      reason: reason.into()
     };
-    // invoke_sync_method!(0, self.command_tx, self.message_tx, method.into_frame()).await?;
-    self.message_tx.send((0, method.into_frame())).unwrap();
+    let frame = rpc.call(0, message_tx, method.into_frame()).await?;
+    let _update_secret_ok = unwrap_frame_variant!(frame, ConnectionUpdateSecretOk);
+    info!("connection secret updated");
+
     Ok(())
   }
 
-  async fn handshake(&self, reader: &mut FrameReader, writer: &mut FrameWriter) -> Result<()> {
+  pub(crate) fn spawn_secret_refresher(&self, provider: Arc<dyn CredentialsProvider>, mut expires_in: Option<Duration>) {
+    let rpc = self.rpc.clone();
+    let message_tx = self.message_tx.clone();
+    let mut close_rx = self.close_tx.subscribe();
+
+    tokio::spawn(async move {
+      while let Some(lifetime) = expires_in {
+        // refresh once most of the lifetime has passed, leaving room for retries
+        let refresh_delay = tokio::time::sleep(lifetime.mul_f64(SECRET_REFRESH_RATIO));
+
+        tokio::select! {
+          _ = refresh_delay => {},
+          _ = close_rx.recv() => {
+            break;
+          }
+        }
+
+        let result = match provider.fetch().await {
+          Ok(credentials) => {
+            Self::invoke_update_secret(&rpc, &message_tx, &credentials.secret, "Token refresh").await
+              .map(|_| credentials.expires_in)
+          },
+          Err(err) => Err(err)
+        };
+
+        expires_in = match result {
+          Ok(next_expires_in) => next_expires_in,
+          Err(err) => {
+            warn!("failed to refresh connection secret: {}", err);
+            Some(SECRET_RETRY_DELAY)
+          }
+        };
+      }
+
+      info!("exit secret refresher");
+    }.instrument(self.span.clone()));
+  }
+
+  // closes the connection for every clone of the handle, returns once the server confirmed it
+  pub async fn close(&self) -> Result<()> {
+    let mut closing = false;
+    self.state_tx.send_if_modified(|state| {
+      closing = state.is_open();
+      if closing {
+        *state = ConnectionState::Closing;
+      }
+      closing
+    });
+
+    if closing {
+      let method = ConnectionClose {
+        reply_code: REPLY_SUCCESS,
+        reply_text: "Connection closed".into(),
+        class_id: 0,
+        method_id: 0,
+      };
+      self.message_tx.send((0, method.into_frame()).into())?;
+    }
+
+    let mut state_rx = self.state_tx.subscribe();
+    let closed = tokio::time::timeout(CLOSE_TIMEOUT, async {
+      while !state_rx.borrow_and_update().is_closed() {
+        state_rx.changed().await?;
+      }
+      Ok::<(), watch::error::RecvError>(())
+    }).await;
+
+    if closed.is_err() {
+      warn!("close-ok wasn't received in {:?}, dropping the connection", CLOSE_TIMEOUT);
+      self.state_tx.send_replace(ConnectionState::Closed(None));
+      let _ = self.close_tx.send(());
+      self.events().emit(ConnectionEvent::Closed { reply_code: REPLY_SUCCESS, reply_text: "Connection closed".into() });
+    }
+
+    Ok(())
+  }
+
+  async fn handshake(&mut self, reader: &mut TransportReader, writer: &mut TransportWriter) -> Result<()> {
     info!("handshake started");
     writer.write_binary(&PROTOCOL_HEADER).await?;
 
-    let (_, frame) = reader.next_frame().await?;
-    let _start_method = unwrap_frame_variant!(frame, ConnectionStart);
+    let frame = Self::next_handshake_frame(reader).await?;
+    let start_method = unwrap_frame_variant!(frame, ConnectionStart);
+    self.server = Arc::new(ServerInfo::from(start_method));
+    info!("connected to {:?} {:?}", self.server.properties.get(&"product".into()), self.server.version);
+    if self.arguments.blocked_publish_timeout.is_some() && !self.server.capabilities.connection_blocked() {
+      warn!("server doesn't support connection.blocked, blocked_publish_timeout is ignored");
+    }
 
-    let client_properties: PropTable = HashMap::from([
+    let mut client_properties: PropTable = HashMap::from([
       ("product".into(), Property::LongStr(PRODUCT.into())),
       ("platform".into(), Property::LongStr(PLATFORM.into())),
       ("copyright".into(), Property::LongStr(COPYRIGHT.into())),
-      ("information".into(), Property::LongStr(INFORMATION.into()))
+      ("information".into(), Property::LongStr(INFORMATION.into())),
+      ("capabilities".into(), Property::Table(self.server.capabilities.client_capabilities()))
     ]);
+    if let Some(name) = &self.arguments.connection_name {
+      client_properties.insert("connection_name".into(), Property::LongStr(name.as_str().into()));
+    }
+    let mechanism = self.select_mechanism()?;
+    let login = self.arguments.address.login.as_str();
+    let password = self.arguments.address.password.as_str();
+    info!("authenticating with {} mechanism", mechanism.name());
+
     let start_ok_method = ConnectionStartOk {
       properties: client_properties,
-      mechanism: ShortStr(DEFAULT_AUTH_MECHANISM.to_string()),
-      response: LongStr(format!("\x00{}\x00{}", self.arguments.address.login.as_str(), self.arguments.address.password)),
+      mechanism: ShortStr(mechanism.name().to_string()),
+      response: mechanism.response(login, password)?.into(),
       locale: ShortStr(DEFAULT_LOCALE.to_string()),
     };
 
     writer.dispatch(0, start_ok_method.into_frame()).await?;
-    let (_, frame) = reader.next_frame().await?;
-    let _tune_method = unwrap_frame_variant!(frame, ConnectionTune);
+
+    let tune_method = loop {
+      let frame = match Self::next_handshake_frame(reader).await {
+        Ok(frame) => frame,
+        Err(err) if err.is::<ConnectionError>() || self.server.capabilities.authentication_failure_close() => {
+          return Err(err);
+        },
+        // servers without authentication_failure_close drop the socket on a failed login
+        Err(err) => {
+          return Err(ConnectionError::AuthenticationFailure(err.to_string()).into());
+        }
+      };
+
+      match frame {
+        Frame::ConnectionSecure(secure) => {
+          let response = mechanism.challenge(&secure.challenge.0, login, password)?;
+          let secure_ok_method = ConnectionSecureOk { response: response.into() };
+          writer.dispatch(0, secure_ok_method.into_frame()).await?;
+        },
+        Frame::ConnectionTune(tune) => {
+          break tune;
+        },
+        frame => {
+          bail!("Unexpected frame during handshake: {:?}", frame);
+        }
+      }
+    };
+
+    // the handles aren't shared yet, so this doesn't copy the arguments
+    let arguments = Arc::make_mut(&mut self.arguments);
+    // the tuned values are unsigned on the wire, a server limit above i16::MAX must not wrap to a lower one
+    arguments.max_channels = negotiate(arguments.max_channels as u16, tune_method.chan_max as u16) as Short;
+    arguments.max_frame_size = negotiate(arguments.max_frame_size as u32, tune_method.frame_max as u32) as Int;
+    // a client that opted out of heartbeats keeps them disabled whatever the server proposes
+    if arguments.heartbeat_interval != 0 {
+      arguments.heartbeat_interval = negotiate(arguments.heartbeat_interval as u16, tune_method.heartbeat as u16) as Short;
+    }
+    self.id_allocator.set_max(arguments.max_channels);
 
     let tune_ok_method = ConnectionTuneOk {
-      chan_max: self.arguments.max_channels,
-      frame_max: self.arguments.max_frame_size,
-      heartbeat: self.arguments.heartbeat_interval
+      chan_max: arguments.max_channels,
+      frame_max: arguments.max_frame_size,
+      heartbeat: arguments.heartbeat_interval
     };
 
     writer.dispatch(0, tune_ok_method.into_frame()).await?;
+    writer.set_frame_max(self.arguments.max_frame_size as u32 as usize);
 
     let open_method = ConnectionOpen {
       vhost: self.arguments.address.vhost.clone().into(),
@@ -124,134 +394,265 @@ impl Connection {
 
     writer.dispatch(0, open_method.into_frame()).await?;
 
-    let (_, frame) = reader.next_frame().await?;
+    let frame = Self::next_handshake_frame(reader).await?;
     let _open_ok_method = unwrap_frame_variant!(frame, ConnectionOpenOk);
 
     Ok(())
   }
 
+  fn select_mechanism(&self) -> Result<Arc<dyn SaslMechanism>> {
+    let offered = &self.server.mechanisms;
+
+    self.arguments.auth_mechanisms.iter()
+      .find(|mechanism| offered.iter().any(|name| name == mechanism.name()))
+      .cloned()
+      .ok_or_else(|| ConnectionError::UnsupportedMechanisms(offered.clone()).into())
+  }
+
+  async fn next_handshake_frame(reader: &mut TransportReader) -> Result<Frame> {
+    let (_, frame) = reader.next_frame().await?;
+
+    if let Frame::ConnectionClose(close) = frame {
+      let error = if close.reply_code == ACCESS_REFUSED {
+        ConnectionError::AuthenticationFailure(close.reply_text.0)
+      } else {
+        ConnectionError::Closed { reply_code: close.reply_code, reply_text: close.reply_text.0 }
+      };
+
+      return Err(error.into());
+    }
+
+    Ok(frame)
+  }
+
   fn spawn_connection_handlers(
     &self,
-    mut reader: FrameReader,
-    mut writer: FrameWriter,
-    mut outgoing_rx: UnboundedReceiver<FrameEnvelope>,
+    mut reader: TransportReader,
+    mut writer: TransportWriter,
+    mut outgoing_rx: OutgoingRx,
     mut command_rx: UnboundedReceiver<Command>
   ) {
-    let mut channel_manager = ChannelManager::new();
+    // the default channel task picks up the connection span
+    let _span = self.span.enter();
+    let mut channel_manager = ChannelManager::new(self.metrics.clone());
 
     let (channel_tx, channel_rx) = mpsc::unbounded_channel();
     let default_channel = DefaultAmqChannel::open(
       self.message_tx.clone(),
       channel_rx,
-      self.close_tx.clone()
+      self.close_tx.clone(),
+      self.blocked_tx.clone(),
+      self.state_tx.clone(),
+      self.rpc.clone(),
+      self.arguments.events.clone()
     ).unwrap();
     channel_manager.register_channel(default_channel.id, channel_tx);
 
-    let mut pending_frames: HashMap<ChannelId, ContentFrame> = HashMap::new();
-    let heartbeat_interval = self.arguments.heartbeat_interval;
+    let heartbeat = heartbeat_period(self.arguments.heartbeat_interval);
     let close_tx = self.close_tx.clone();
     let mut close_rx = self.close_tx.subscribe();
 
-    let outgoing_tx = self.message_tx.clone();
+    let state_tx = self.state_tx.clone();
+    let events = self.arguments.events.clone();
+    let metrics = self.metrics.clone();
 
     tokio::spawn(async move {
-      let mut last_heartbeat = SystemTime::now();
-      loop {
-        let timeout_delay = tokio::time::sleep(Duration::from_secs(heartbeat_interval as u64));
+      // the peer is considered dead after two heartbeat periods without any traffic
+      let liveness_timeout = heartbeat.map(|period| period * 2);
+      let liveness_deadline = tokio::time::sleep(liveness_timeout.unwrap_or_default());
+      tokio::pin!(liveness_deadline);
 
+      let error = loop {
         tokio::select! {
           Some((payload, acker)) = command_rx.recv() => {
             match payload {
-              CommandPayload::RegisterResponder((channel, responder)) => {
-                channel_manager.register_responder(channel, responder);
-              },
               CommandPayload::RegisterChannel((id, incoming_tx)) => {
                 channel_manager.register_channel(id, incoming_tx);
               },
-              CommandPayload::RegisterConsumer(channel, consumer_tag, consumer_tx) => {
-                channel_manager.register_consumer(channel, consumer_tag, consumer_tx);
+              CommandPayload::UnregisterChannel(channel) => {
+                channel_manager.unregister_channel(channel);
               }
             }
             acker.send(()).unwrap();
           },
-          Ok((channel, frame)) = reader.next_frame() => {
-            last_heartbeat = SystemTime::now();
-
-            match &frame {
-              Frame::Heartbeat => {
-                info!("Heartbeat received");
-                // todo!("Do something with heartbeat");
-              }
-              Frame::ContentHeader(..) => {
-                let pending_frame = pending_frames.remove(&channel).unwrap();
-                let content_header = unwrap_frame_variant!(frame, ContentHeader);
-                pending_frames.insert(channel, pending_frame.with_content_header(content_header));
+          received = reader.next_frame() => {
+            let (channel, frame) = match received {
+              Ok(received) => received,
+              Err(err) => {
+                break Some(ConnectionError::Closed { reply_code: 0, reply_text: format!("Connection lost: {}", err) });
               }
-              Frame::ContentBody(..) => {
-                let mut pending_frame = pending_frames.remove(&channel).unwrap();
-                let content_body = unwrap_frame_variant!(frame, ContentBody);
-                pending_frame = pending_frame.with_body(content_body);
-
-                if pending_frame.is_complete() {
-                  channel_manager.dispatch_content_frame(channel, outgoing_tx.clone(), pending_frame);
-                } else {
-                  pending_frames.insert(channel, pending_frame);
-                }
-              }
-              Frame::ChannelOpenOk(..) |
-              Frame::ExchangeDeclareOk(..) |
-              Frame::QueueDeclareOk(..) |
-              Frame::QueueBindOk(..) |
-              Frame::QueueUnbindOk(..) |
-              Frame::BasicConsumeOk(..) => {
-                channel_manager.get_responder(channel).send(frame).unwrap();
-              }
-              Frame::BasicDeliver(..) => {
-                pending_frames.insert(channel, ContentFrame::WithMethod(frame));
-              }
-              _ => {
-                if channel == 0 {
-                  channel_manager.dispatch_channel_frame((channel, frame)).unwrap();
-                } else {
-                  todo!("handle frame {:?}", frame);
+            };
+
+            if let Some(timeout) = liveness_timeout {
+              liveness_deadline.as_mut().reset(Instant::now() + timeout);
+            }
+            debug!(channel, method = frame.name(), "frame received");
+
+            match frame {
+              Frame::Heartbeat => {}
+              frame => {
+                if let Err(err) = channel_manager.dispatch_channel_frame((channel, frame)) {
+                  warn!("failed to dispatch frame to channel {}: {}", channel, err);
                 }
               }
             }
           },
-          _ = timeout_delay => {
-            if SystemTime::now().duration_since(last_heartbeat).unwrap().as_secs() >  heartbeat_interval as u64  * 2 {
-              println!("Missing heartbeat");
-              close_tx.send(()).unwrap();
-            }
+          _ = &mut liveness_deadline, if liveness_timeout.is_some() => {
+            let timeout = liveness_timeout.unwrap_or_default();
+            warn!("no traffic from the server for {:?}, closing the connection", timeout);
+            metrics.heartbeat_missed();
+            break Some(ConnectionError::HeartbeatTimeout(timeout));
           },
           _ = close_rx.recv() => {
-            break;
+            break None;
           }
         }
+      };
+
+      // the state is updated before the channel queues are dropped, so channels can report the cause
+      if let Some(error) = error {
+        Self::connection_lost(&state_tx, &close_tx, &events, error);
       }
+
       info!("exit reader loop");
-    });
+    }.instrument(self.span.clone()));
 
     let mut close_rx = self.close_tx.subscribe();
+    let close_tx = self.close_tx.clone();
+    let state_tx = self.state_tx.clone();
+    let events = self.arguments.events.clone();
     tokio::spawn(async move {
-      loop {
-        let heartbeat_delay = tokio::time::sleep(Duration::from_secs(heartbeat_interval as u64));
+      // heartbeats are sent at half the negotiated interval, and only when nothing else was written
+      let send_interval = heartbeat.map(|period| period / 2);
+      let idle_deadline = tokio::time::sleep(send_interval.unwrap_or_default());
+      tokio::pin!(idle_deadline);
 
-        tokio::select! {
-          Some((channel, frame)) = outgoing_rx.recv() => {
-            writer.dispatch(channel, frame).await.unwrap();
+      loop {
+        let written = tokio::select! {
+          Some(outgoing) = outgoing_rx.recv() => {
+            Self::write_batch(&mut writer, &mut outgoing_rx, outgoing).await
           },
-          _ = heartbeat_delay => {
-            info!("heartbeat delivered");
-            writer.dispatch(0, Frame::Heartbeat).await.unwrap();
+          _ = &mut idle_deadline, if send_interval.is_some() => {
+            debug!("sending heartbeat");
+            writer.dispatch(0, Frame::Heartbeat).await
           },
           _ = close_rx.recv() => {
             break;
           }
         };
+
+        if let Err(err) = written {
+          warn!("failed to write to the socket: {}", err);
+          // the reader stops on the close signal without a reason of its own, so the failure is recorded here
+          let error = ConnectionError::Closed { reply_code: 0, reply_text: format!("Connection lost: {}", err) };
+          Self::connection_lost(&state_tx, &close_tx, &events, error);
+          break;
+        }
+
+        if let Some(interval) = send_interval {
+          idle_deadline.as_mut().reset(Instant::now() + interval);
+        }
       }
 
       info!("exit writer loop");
+    }.instrument(self.span.clone()));
+  }
+
+  // a no-op when the connection is already closed, whichever task notices the failure first reports it
+  fn connection_lost(
+    state_tx: &watch::Sender<ConnectionState>,
+    close_tx: &broadcast::Sender<()>,
+    events: &ConnectionEvents,
+    error: ConnectionError
+  ) {
+    let lost = state_tx.send_if_modified(|state| {
+      let closing = !state.is_closed();
+      if closing {
+        *state = ConnectionState::Closed(Some(error.clone()));
+      }
+      closing
     });
+
+    if lost {
+      let _ = close_tx.send(());
+      events.emit(ConnectionEvent::Error(error.clone()));
+      events.emit(ConnectionEvent::Closed { reply_code: 0, reply_text: error.to_string() });
+    }
+  }
+
+  // everything already queued goes out with the same flush, up to a batch limit;
+  // a frame that fails to encode is dropped on its own, only socket errors end the connection
+  async fn write_batch(
+    writer: &mut TransportWriter,
+    outgoing_rx: &mut OutgoingRx,
+    first: OutgoingFrame
+  ) -> Result<()> {
+    Self::enqueue(writer, first);
+
+    while writer.buffered() < MAX_WRITE_BATCH {
+      match outgoing_rx.try_recv() {
+        Ok(outgoing) => Self::enqueue(writer, outgoing),
+        Err(_) => break
+      }
+    }
+
+    writer.flush().await?;
+    outgoing_rx.written();
+    Ok(())
+  }
+
+  fn enqueue(writer: &mut TransportWriter, outgoing: OutgoingFrame) {
+    if let Err(err) = writer.enqueue(outgoing) {
+      warn!("dropping a frame which failed to encode: {}", err);
+    }
+  }
+}
+
+// zero disables heartbeating
+fn heartbeat_period(interval: Short) -> Option<Duration> {
+  (interval != 0).then(|| Duration::from_secs(interval as u16 as u64))
+}
+
+// zero means "no limit" for the tuned values, otherwise the lower one wins
+fn negotiate<T: Ord + Default>(client: T, server: T) -> T {
+  if client == T::default() {
+    server
+  } else if server == T::default() {
+    client
+  } else {
+    client.min(server)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn negotiate_takes_the_lower_limit() {
+    assert_eq!(negotiate(20_u16, 100), 20);
+    assert_eq!(negotiate(131072_u32, 4096), 4096);
+  }
+
+  #[test]
+  fn negotiate_treats_zero_as_no_limit() {
+    assert_eq!(negotiate(0_u16, 100), 100);
+    assert_eq!(negotiate(20_u16, 0), 20);
+    assert_eq!(negotiate(0_u16, 0), 0);
+  }
+
+  #[test]
+  fn negotiate_keeps_the_client_limit_below_a_large_server_one() {
+    let server_chan_max: Short = u16::MAX as Short;
+
+    assert_eq!(negotiate(20_u16, server_chan_max as u16), 20);
+    assert_eq!(negotiate(0_u16, server_chan_max as u16) as Short, server_chan_max);
+  }
+
+  #[test]
+  fn heartbeat_period_is_disabled_by_zero() {
+    assert_eq!(heartbeat_period(0), None);
+    assert_eq!(heartbeat_period(60), Some(Duration::from_secs(60)));
+    assert_eq!(heartbeat_period(u16::MAX as Short), Some(Duration::from_secs(u16::MAX as u64)));
   }
 }
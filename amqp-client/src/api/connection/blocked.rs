@@ -0,0 +1,40 @@
+use std::time::Duration;
+use tokio::sync::watch;
+use crate::api::connection::error::ConnectionError;
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BlockedState {
+  #[default]
+  Unblocked,
+  // the broker raised a resource alarm (memory, disk) and stopped reading from the connection
+  Blocked(String),
+}
+
+impl BlockedState {
+  pub fn is_blocked(&self) -> bool {
+    matches!(self, BlockedState::Blocked(_))
+  }
+}
+
+pub(crate) async fn wait_unblocked(blocked_rx: &watch::Receiver<BlockedState>, timeout: Duration) -> Result<()> {
+  let mut blocked_rx = blocked_rx.clone();
+  let unblocked = tokio::time::timeout(timeout, async {
+    while blocked_rx.borrow_and_update().is_blocked() {
+      blocked_rx.changed().await?;
+    }
+    Ok::<(), watch::error::RecvError>(())
+  }).await;
+
+  match unblocked {
+    Ok(Ok(_)) => Ok(()),
+    Ok(Err(_)) => Err(ConnectionError::Closed { reply_code: 0, reply_text: "Connection closed while blocked".into() }.into()),
+    Err(_) => {
+      let reason = match &*blocked_rx.borrow() {
+        BlockedState::Blocked(reason) => reason.clone(),
+        BlockedState::Unblocked => String::new()
+      };
+      Err(ConnectionError::Blocked(reason).into())
+    }
+  }
+}
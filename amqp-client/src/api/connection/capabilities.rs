@@ -0,0 +1,66 @@
+use crate::protocol::frame::ConnectionStart;
+use crate::protocol::types::{Byte, Property, PropTable};
+
+pub static CONNECTION_BLOCKED: &str = "connection.blocked";
+pub static AUTHENTICATION_FAILURE_CLOSE: &str = "authentication_failure_close";
+
+// capabilities the client is able to handle, advertised only when the server offers them too
+pub static CLIENT_CAPABILITIES: [&str; 2] = [AUTHENTICATION_FAILURE_CLOSE, CONNECTION_BLOCKED];
+
+#[derive(Default, Debug, Clone)]
+pub struct Capabilities(PropTable);
+
+impl Capabilities {
+  pub fn new(table: PropTable) -> Self {
+    Self(table)
+  }
+
+  pub fn has(&self, name: &str) -> bool {
+    matches!(self.0.get(&name.into()), Some(Property::Bool(true)))
+  }
+
+  pub fn connection_blocked(&self) -> bool {
+    self.has(CONNECTION_BLOCKED)
+  }
+
+  pub fn authentication_failure_close(&self) -> bool {
+    self.has(AUTHENTICATION_FAILURE_CLOSE)
+  }
+
+  pub fn as_table(&self) -> &PropTable {
+    &self.0
+  }
+
+  pub(crate) fn client_capabilities(&self) -> PropTable {
+    CLIENT_CAPABILITIES.iter()
+      .filter(|name| self.has(name))
+      .map(|name| ((*name).into(), Property::Bool(true)))
+      .collect()
+  }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ServerInfo {
+  pub version: (Byte, Byte),
+  pub properties: PropTable,
+  pub capabilities: Capabilities,
+  pub mechanisms: Vec<String>,
+  pub locales: Vec<String>,
+}
+
+impl From<ConnectionStart> for ServerInfo {
+  fn from(start: ConnectionStart) -> Self {
+    let capabilities = match start.properties.get(&"capabilities".into()) {
+      Some(Property::Table(table)) => Capabilities::new(table.clone()),
+      _ => Capabilities::default()
+    };
+
+    Self {
+      version: (start.ver_major, start.ver_minor),
+      properties: start.properties,
+      capabilities,
+      mechanisms: start.mechanisms.0.split_whitespace().map(String::from).collect(),
+      locales: start.locales.0.split_whitespace().map(String::from).collect(),
+    }
+  }
+}
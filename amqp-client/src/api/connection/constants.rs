@@ -3,5 +3,4 @@ pub static PRODUCT: &str = "amqp0.9.1 client";
 pub static PLATFORM: &str = "rust lang";
 pub static COPYRIGHT: &str = "lorem ipsum";
 pub static INFORMATION: &str = "lorem ipsum";
-pub static DEFAULT_AUTH_MECHANISM: &str = "PLAIN";
 pub static DEFAULT_LOCALE: &str = "en_US";
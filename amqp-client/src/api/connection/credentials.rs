@@ -0,0 +1,29 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use crate::Result;
+
+pub type CredentialsFuture<'a> = Pin<Box<dyn Future<Output = Result<Credentials>> + Send + 'a>>;
+
+#[derive(Clone)]
+pub struct Credentials {
+  pub secret: String,
+  // how long the secret stays valid, `None` for secrets which never expire
+  pub expires_in: Option<Duration>,
+}
+
+impl Debug for Credentials {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Credentials")
+      .field("secret", &"***")
+      .field("expires_in", &self.expires_in)
+      .finish()
+  }
+}
+
+// supplies short-lived secrets (e.g. OAuth2 tokens), used as the password on connect
+// and pushed to the broker with connection.update-secret before they expire
+pub trait CredentialsProvider: Debug + Send + Sync {
+  fn fetch(&self) -> CredentialsFuture<'_>;
+}
@@ -0,0 +1,45 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::protocol::types::Short;
+
+pub const REPLY_SUCCESS: Short = 200;
+pub const ACCESS_REFUSED: Short = 403;
+pub const COMMAND_INVALID: Short = 503;
+
+#[derive(Debug, Clone)]
+pub enum ConnectionError {
+  AuthenticationFailure(String),
+  UnsupportedMechanisms(Vec<String>),
+  Blocked(String),
+  Closed { reply_code: Short, reply_text: String },
+  ChannelsExhausted(Short),
+  // nothing, not even a heartbeat, came from the server for this long
+  HeartbeatTimeout(Duration),
+}
+
+impl Display for ConnectionError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ConnectionError::AuthenticationFailure(reason) => {
+        write!(f, "Authentication failed: {}", reason)
+      },
+      ConnectionError::UnsupportedMechanisms(offered) => {
+        write!(f, "None of the server auth mechanisms is supported: {}", offered.join(", "))
+      },
+      ConnectionError::Blocked(reason) => {
+        write!(f, "Connection is blocked by the server: {}", reason)
+      },
+      ConnectionError::Closed { reply_code, reply_text } => {
+        write!(f, "Connection closed with code: {}, reason: {}", reply_code, reply_text)
+      },
+      ConnectionError::ChannelsExhausted(channel_max) => {
+        write!(f, "All {} channels of the connection are in use", channel_max)
+      },
+      ConnectionError::HeartbeatTimeout(timeout) => {
+        write!(f, "Missed heartbeats from the server for {:?}", timeout)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ConnectionError {}
@@ -0,0 +1,104 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use crate::api::connection::error::ConnectionError;
+use crate::protocol::types::{ChannelId, Short};
+
+const EVENTS_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+  Opened,
+  Blocked(String),
+  Unblocked,
+  // emitted for every close, the client's own close carries 200
+  Closed { reply_code: Short, reply_text: String },
+  // the connection failed, e.g. the server closed it with an error code
+  Error(ConnectionError),
+  ChannelClosed { id: ChannelId, reply_code: Short, reply_text: String },
+  // basic.recover completed on a channel
+  Recovered { id: ChannelId, requeue: bool },
+}
+
+type Hook = Arc<dyn Fn(&ConnectionEvent) + Send + Sync>;
+type HookFilter = fn(&ConnectionEvent) -> bool;
+
+// delivers lifecycle events to the registered hooks and to `subscribe` receivers,
+// it is shared by every clone of the arguments and of the connection
+#[derive(Clone)]
+pub struct ConnectionEvents {
+  events_tx: broadcast::Sender<ConnectionEvent>,
+  hooks: Arc<Mutex<Vec<(HookFilter, Hook)>>>,
+}
+
+impl ConnectionEvents {
+  pub fn new() -> Self {
+    Self {
+      events_tx: broadcast::channel(EVENTS_CAPACITY).0,
+      hooks: Default::default(),
+    }
+  }
+
+  // lagging receivers lose the oldest events
+  pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+    self.events_tx.subscribe()
+  }
+
+  pub fn on_open<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::Opened), hook);
+  }
+
+  pub fn on_close<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::Closed { .. }), hook);
+  }
+
+  pub fn on_error<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::Error(_)), hook);
+  }
+
+  // called for both blocked and unblocked
+  pub fn on_blocked<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::Blocked(_) | ConnectionEvent::Unblocked), hook);
+  }
+
+  pub fn on_channel_close<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::ChannelClosed { .. }), hook);
+  }
+
+  pub fn on_recovery<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, hook: F) {
+    self.register(|event| matches!(event, ConnectionEvent::Recovered { .. }), hook);
+  }
+
+  fn register<F: Fn(&ConnectionEvent) + Send + Sync + 'static>(&self, filter: HookFilter, hook: F) {
+    self.hooks.lock().unwrap().push((filter, Arc::new(hook)));
+  }
+
+  pub(crate) fn emit(&self, event: ConnectionEvent) {
+    // hooks may register other hooks, so they run without the lock
+    let hooks: Vec<Hook> = self.hooks.lock().unwrap().iter()
+      .filter(|(filter, _)| filter(&event))
+      .map(|(_, hook)| hook.clone())
+      .collect();
+
+    for hook in hooks {
+      hook(&event);
+    }
+
+    let _ = self.events_tx.send(event);
+  }
+}
+
+impl Default for ConnectionEvents {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Debug for ConnectionEvents {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ConnectionEvents")
+      .field("hooks", &self.hooks.lock().unwrap().len())
+      .field("subscribers", &self.events_tx.receiver_count())
+      .finish()
+  }
+}
@@ -1,4 +1,5 @@
 use tokio::net::TcpStream;
+use tracing::debug;
 use crate::api::connection::options::ConnectionArgs;
 use super::{Connection};
 use crate::Result;
@@ -7,10 +8,27 @@ pub struct ConnectionFactory;
 
 impl ConnectionFactory {
   pub async fn create(uri: &str) -> Result<Connection> {
-    let options = ConnectionArgs::new(uri);
-    println!("Options {:?}", &options);
+    Self::create_with_args(ConnectionArgs::new(uri)).await
+  }
+
+  pub async fn create_with_args(mut options: ConnectionArgs) -> Result<Connection> {
+    let credentials = match options.credentials_provider.clone() {
+      Some(provider) => {
+        let credentials = provider.fetch().await?;
+        options.address.password = credentials.secret.clone();
+        Some((provider, credentials))
+      },
+      None => None
+    };
+
+    debug!(?options, "connecting");
     let stream = TcpStream::connect((options.address.host.clone(), options.address.port)).await?;
     let connection = Connection::open(stream, options).await?;
+
+    if let Some((provider, credentials)) = credentials {
+      connection.spawn_secret_refresher(provider, credentials.expires_in);
+    }
+
     Ok(connection)
   }
 }
@@ -1,11 +1,37 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
+use crate::api::connection::credentials::CredentialsProvider;
+use crate::api::connection::events::ConnectionEvents;
+use crate::protocol::compression::{CompressionOpts, DEFAULT_MAX_DECOMPRESSED_SIZE};
+use crate::api::connection::sasl::{AmqPlain, Plain, SaslMechanism};
 
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct ConnectionArgs {
   pub address: ConnectionAddress,
   pub max_channels: i16,
   pub max_frame_size: i32,
+  // seconds, zero disables heartbeats
   pub heartbeat_interval: i16,
+  // in order of preference, the first one offered by the server is used
+  pub auth_mechanisms: Vec<Arc<dyn SaslMechanism>>,
+  pub credentials_provider: Option<Arc<dyn CredentialsProvider>>,
+  // when set, publish waits up to this long for a blocked connection to resume, then fails;
+  // ignored when the server doesn't support connection.blocked
+  pub blocked_publish_timeout: Option<Duration>,
+  // compresses published bodies, deliveries are decompressed based on their content-encoding regardless
+  pub compression: Option<CompressionOpts>,
+  // deliveries inflating past this many bytes are rejected instead of decompressed
+  pub max_decompressed_size: usize,
+  // how long publish waits for a channel paused with channel.flow, without it publish fails right away
+  pub flow_publish_timeout: Option<Duration>,
+  // hooks registered here also see the open event, later ones can be added with `Connection::events`
+  pub events: ConnectionEvents,
+  // body bytes publishers may queue ahead of the writer before `publish` waits, zero for no limit
+  pub outgoing_queue_capacity: usize,
+  // shown by the broker's management UI and used as the metrics label, defaults to host:port/vhost
+  pub connection_name: Option<String>,
 }
 
 impl ConnectionArgs {
@@ -14,13 +40,29 @@ impl ConnectionArgs {
       address: ConnectionAddress::from(uri),
       max_channels: 20,
       max_frame_size: 128*1024,
-      heartbeat_interval: 60
+      heartbeat_interval: 60,
+      auth_mechanisms: vec![Arc::new(Plain), Arc::new(AmqPlain)],
+      credentials_provider: None,
+      blocked_publish_timeout: None,
+      compression: None,
+      max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+      flow_publish_timeout: None,
+      events: ConnectionEvents::new(),
+      outgoing_queue_capacity: 4 * 1024 * 1024,
+      connection_name: None
+    }
+  }
+
+  pub(crate) fn display_name(&self) -> String {
+    match &self.connection_name {
+      Some(name) => name.clone(),
+      None => format!("{}:{}/{}", self.address.host, self.address.port, self.address.vhost)
     }
   }
 }
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ConnectionAddress {
   pub host: String,
   pub port: u16,
@@ -29,6 +71,40 @@ pub struct ConnectionAddress {
   pub vhost: String,
 }
 
+// a provider's own Debug could print the secret it holds, so only its presence is shown
+impl Debug for ConnectionArgs {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ConnectionArgs")
+      .field("address", &self.address)
+      .field("max_channels", &self.max_channels)
+      .field("max_frame_size", &self.max_frame_size)
+      .field("heartbeat_interval", &self.heartbeat_interval)
+      .field("auth_mechanisms", &self.auth_mechanisms)
+      .field("credentials_provider", &self.credentials_provider.as_ref().map(|_| "***"))
+      .field("blocked_publish_timeout", &self.blocked_publish_timeout)
+      .field("compression", &self.compression)
+      .field("max_decompressed_size", &self.max_decompressed_size)
+      .field("flow_publish_timeout", &self.flow_publish_timeout)
+      .field("events", &self.events)
+      .field("outgoing_queue_capacity", &self.outgoing_queue_capacity)
+      .field("connection_name", &self.connection_name)
+      .finish()
+  }
+}
+
+// the password never shows up in logs, not even at debug level
+impl Debug for ConnectionAddress {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ConnectionAddress")
+      .field("host", &self.host)
+      .field("port", &self.port)
+      .field("login", &self.login)
+      .field("password", &"***")
+      .field("vhost", &self.vhost)
+      .finish()
+  }
+}
+
 impl From<&str> for ConnectionAddress {
   fn from(uri: &str) -> Self {
     let url = Url::parse(uri).unwrap();
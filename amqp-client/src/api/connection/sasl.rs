@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use anyhow::bail;
+use crate::protocol::enc::Encode;
+use crate::protocol::types::{LongStr, Property};
+use crate::Result;
+
+pub trait SaslMechanism: Debug + Send + Sync {
+  fn name(&self) -> &str;
+
+  fn response(&self, login: &str, password: &str) -> Result<Vec<u8>>;
+
+  // answers connection.secure, mechanisms without challenge rounds reject it
+  fn challenge(&self, _challenge: &[u8], _login: &str, _password: &str) -> Result<Vec<u8>> {
+    bail!("{} mechanism doesn't support challenges", self.name())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Plain;
+
+impl SaslMechanism for Plain {
+  fn name(&self) -> &str {
+    "PLAIN"
+  }
+
+  fn response(&self, login: &str, password: &str) -> Result<Vec<u8>> {
+    Ok(format!("\x00{}\x00{}", login, password).into_bytes())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct AmqPlain;
+
+impl SaslMechanism for AmqPlain {
+  fn name(&self) -> &str {
+    "AMQPLAIN"
+  }
+
+  // field table content without the leading table size
+  fn response(&self, login: &str, password: &str) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_field_value_pair(("LOGIN".into(), Property::LongStr(LongStr(login.into()))))?;
+    buf.write_field_value_pair(("PASSWORD".into(), Property::LongStr(LongStr(password.into()))))?;
+    Ok(buf)
+  }
+}
+
+// relies on the identity established by the transport, e.g. a TLS client certificate
+#[derive(Debug, Clone)]
+pub struct External;
+
+impl SaslMechanism for External {
+  fn name(&self) -> &str {
+    "EXTERNAL"
+  }
+
+  fn response(&self, _login: &str, _password: &str) -> Result<Vec<u8>> {
+    Ok(vec![])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::frame::{ConnectionSecure, ConnectionStartOk};
+
+  #[test]
+  fn amqplain_response_keeps_long_credentials_intact() {
+    let password = "x".repeat(1024);
+    let response = AmqPlain.response("guest", &password).unwrap();
+    let start_ok = ConnectionStartOk {
+      properties: Default::default(),
+      mechanism: "AMQPLAIN".into(),
+      response: response.clone().into(),
+      locale: "en_US".into()
+    };
+
+    let decoded = ConnectionStartOk::from_raw_repr(&start_ok.to_raw_repr());
+
+    assert_eq!(decoded.response.0, response);
+  }
+
+  #[test]
+  fn challenges_may_be_binary() {
+    let secure = ConnectionSecure { challenge: vec![0xff, 0x00, 0xfe].into() };
+
+    let decoded = ConnectionSecure::from_raw_repr(&secure.to_raw_repr());
+
+    assert_eq!(decoded.challenge.0, [0xff, 0x00, 0xfe]);
+  }
+}
@@ -0,0 +1,21 @@
+use crate::api::connection::error::ConnectionError;
+
+#[derive(Debug, Clone, Default)]
+pub enum ConnectionState {
+  #[default]
+  Open,
+  // close was requested, waiting for the server to confirm it
+  Closing,
+  // carries the reason when the connection wasn't closed by the client
+  Closed(Option<ConnectionError>),
+}
+
+impl ConnectionState {
+  pub fn is_open(&self) -> bool {
+    matches!(self, ConnectionState::Open)
+  }
+
+  pub fn is_closed(&self) -> bool {
+    matches!(self, ConnectionState::Closed(_))
+  }
+}
@@ -1,52 +1,111 @@
-use log::info;
-use tokio::sync::broadcast;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::sync::Arc;
+use tracing::{info, warn, Instrument};
+use tokio::sync::{broadcast, watch};
+use tokio::sync::mpsc::UnboundedReceiver;
 
 use crate::protocol::types::{ChannelId};
 use crate::{Result};
+use crate::building_blocks::{OutgoingTx, RpcSlot};
 use crate::protocol::frame::{FrameEnvelope, Frame};
-use crate::protocol::frame::ConnectionCloseOk;
+use crate::protocol::frame::{ConnectionClose, ConnectionCloseOk};
+use crate::api::connection::{BlockedState, ConnectionError, ConnectionEvent, ConnectionEvents, ConnectionState};
+use crate::api::connection::error::{COMMAND_INVALID, REPLY_SUCCESS};
 
 pub struct DefaultAmqChannel {
   pub id: ChannelId,
-  outgoing_tx: UnboundedSender<FrameEnvelope>,
+  outgoing_tx: OutgoingTx,
 }
 
 impl DefaultAmqChannel {
   pub fn open(
-    outgoing_tx: UnboundedSender<FrameEnvelope>,
+    outgoing_tx: OutgoingTx,
     incoming_rx: UnboundedReceiver<FrameEnvelope>,
     close_tx: broadcast::Sender<()>,
+    blocked_tx: Arc<watch::Sender<BlockedState>>,
+    state_tx: Arc<watch::Sender<ConnectionState>>,
+    rpc: Arc<RpcSlot>,
+    events: ConnectionEvents,
   ) -> Result<Self> {
     let channel = Self { id: 0, outgoing_tx };
-    channel.spawn_incoming_msg_handler(incoming_rx, close_tx);
+    channel.spawn_incoming_msg_handler(incoming_rx, close_tx, blocked_tx, state_tx, rpc, events);
 
     Ok(channel)
   }
 
-  fn spawn_incoming_msg_handler(&self, mut incoming_rx: UnboundedReceiver<FrameEnvelope>, close_tx: broadcast::Sender<()>) {
+  fn spawn_incoming_msg_handler(
+    &self,
+    mut incoming_rx: UnboundedReceiver<FrameEnvelope>,
+    close_tx: broadcast::Sender<()>,
+    blocked_tx: Arc<watch::Sender<BlockedState>>,
+    state_tx: Arc<watch::Sender<ConnectionState>>,
+    rpc: Arc<RpcSlot>,
+    events: ConnectionEvents
+  ) {
     let outgoing_tx = self.outgoing_tx.clone();
     tokio::spawn(async move {
       while let Some((_, frame)) = incoming_rx.recv().await {
         match frame {
           Frame::ConnectionClose(connection_close) => {
             info!("Connection closed with code: {}, reason: {}", connection_close.reply_code, connection_close.reply_text.0);
-            outgoing_tx.send((0, ConnectionCloseOk {}.into_frame())).unwrap();
+            outgoing_tx.send((0, ConnectionCloseOk {}.into_frame()).into()).unwrap();
+            let reply_code = connection_close.reply_code;
+            let reply_text = connection_close.reply_text.0;
+            let error = ConnectionError::Closed { reply_code, reply_text: reply_text.clone() };
+            state_tx.send_replace(ConnectionState::Closed(Some(error.clone())));
             close_tx.send(()).unwrap();
+
+            if reply_code != REPLY_SUCCESS {
+              events.emit(ConnectionEvent::Error(error));
+            }
+            events.emit(ConnectionEvent::Closed { reply_code, reply_text });
             break;
           },
           Frame::ConnectionCloseOk(_) => {
             info!("connection close-ok received");
+            state_tx.send_replace(ConnectionState::Closed(None));
             close_tx.send(()).unwrap();
+            events.emit(ConnectionEvent::Closed { reply_code: REPLY_SUCCESS, reply_text: "Connection closed".into() });
             break;
           }
-          _ => {
-            todo!("Implement handler")
+          frame @ Frame::ConnectionUpdateSecretOk(_) => {
+            rpc.resolve(frame);
+          }
+          Frame::ConnectionBlocked(blocked) => {
+            warn!("Connection blocked, reason: {}", blocked.reason.0);
+            blocked_tx.send_replace(BlockedState::Blocked(blocked.reason.0.clone()));
+            events.emit(ConnectionEvent::Blocked(blocked.reason.0));
+          }
+          Frame::ConnectionUnblocked(_) => {
+            info!("Connection unblocked");
+            blocked_tx.send_replace(BlockedState::Unblocked);
+            events.emit(ConnectionEvent::Unblocked);
+          }
+          frame => {
+            // the server and the client disagree about the protocol, the connection can't be trusted anymore;
+            // the loop keeps running for the close-ok
+            warn!("unexpected frame on channel 0, closing the connection: {:?}", frame);
+            let closing = state_tx.send_if_modified(|state| {
+              let closing = state.is_open();
+              if closing {
+                *state = ConnectionState::Closing;
+              }
+              closing
+            });
+            if closing {
+              let method = ConnectionClose {
+                reply_code: COMMAND_INVALID,
+                reply_text: "Unexpected frame on channel 0".into(),
+                class_id: 0,
+                method_id: 0,
+              };
+              let _ = outgoing_tx.send((0, method.into_frame()).into());
+            }
           }
         }
       }
 
+      rpc.close();
       info!("exited default channel loop");
-    });
+    }.in_current_span());
   }
 }
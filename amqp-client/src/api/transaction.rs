@@ -0,0 +1,37 @@
+use tracing::info;
+use crate::api::channel::AmqChannel;
+use crate::Result;
+
+pub struct Transaction<'a> {
+  channel: &'a AmqChannel,
+  finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+  pub(crate) fn new(channel: &'a AmqChannel) -> Self {
+    Self { channel, finished: false }
+  }
+
+  pub fn channel(&self) -> &AmqChannel {
+    self.channel
+  }
+
+  pub async fn commit(mut self) -> Result<()> {
+    self.finished = true;
+    self.channel.tx_commit().await
+  }
+
+  pub async fn rollback(mut self) -> Result<()> {
+    self.finished = true;
+    self.channel.tx_rollback().await
+  }
+}
+
+impl Drop for Transaction<'_> {
+  fn drop(&mut self) {
+    if !self.finished {
+      info!("transaction dropped without commit, rolling back channel {}", self.channel.id);
+      self.channel.queue_tx_rollback();
+    }
+  }
+}
@@ -0,0 +1,140 @@
+// internals the benches in `benches/` work with, not a stable API
+use std::collections::HashMap;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, DuplexStream};
+use crate::Result;
+use crate::protocol::frame::{BasicConsumeOk, BasicDeliver, ChannelCloseOk, ChannelOpenOk, ConnectionCloseOk,
+                             ConnectionOpenOk, ConnectionStart, ConnectionTune, ExchangeDeclareOk, OutgoingFrame,
+                             QueueBindOk, QueueDeclareOk};
+use crate::protocol::types::{ChannelId, Property};
+
+pub use crate::protocol::dec::Decode;
+pub use crate::protocol::enc::Encode;
+pub use crate::protocol::frame::{ContentBody, ContentFrame, ContentHeader, Frame};
+pub use crate::protocol::message::MessageProperties;
+pub use crate::protocol::net::{FrameReader, FrameWriter};
+
+const FRAME_MAX: i32 = 128 * 1024;
+
+// both ends of an in-memory transport, one for `Connection::open_transport`, one for `serve_loopback`
+pub fn memory_transport() -> (DuplexStream, DuplexStream) {
+  tokio::io::duplex(FRAME_MAX as usize * 4)
+}
+
+// deliveries as the server would send them, `body_len` bytes each, ready for a `FrameReader`
+pub async fn record_deliveries(count: usize, body_len: usize) -> Result<Vec<u8>> {
+  let mut writer = FrameWriter::new(vec![]);
+  writer.set_frame_max(FRAME_MAX as usize);
+  let body = Bytes::from(vec![7u8; body_len]);
+
+  for delivery_tag in 1..=count as i64 {
+    writer.enqueue(delivery(1, "bench", delivery_tag, "bench", MessageProperties::default(), body.clone()))?;
+  }
+
+  writer.flush().await?;
+  Ok(writer.into_inner())
+}
+
+fn delivery(
+  channel: ChannelId,
+  consumer_tag: &str,
+  delivery_tag: i64,
+  routing_key: &str,
+  properties: MessageProperties,
+  body: Bytes
+) -> OutgoingFrame {
+  let deliver = BasicDeliver {
+    consumer_tag: consumer_tag.into(),
+    deliver_tag: delivery_tag,
+    redelivered: false,
+    exchange: "".into(),
+    routing_key: routing_key.into()
+  };
+  let header = ContentHeader::new(60, body.len() as i64, properties);
+
+  OutgoingFrame::Content(channel, deliver.into_frame(), Box::new(header), body)
+}
+
+// a broker stand-in which delivers every published message back to the consumers of its channel,
+// just enough of the protocol to measure the client without the network and a real server
+pub async fn serve_loopback<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> Result<()> {
+  let (mut read_half, write_half) = tokio::io::split(stream);
+  let mut protocol_header = [0u8; 8];
+  read_half.read_exact(&mut protocol_header).await?;
+
+  let mut reader = FrameReader::new(read_half);
+  let mut writer = FrameWriter::new(write_half);
+  writer.set_frame_max(FRAME_MAX as usize);
+
+  let start = ConnectionStart {
+    ver_major: 0,
+    ver_minor: 9,
+    properties: HashMap::from([("product".into(), Property::LongStr("loopback".into()))]),
+    mechanisms: "PLAIN AMQPLAIN".into(),
+    locales: "en_US".into()
+  };
+  writer.dispatch(0, start.into_frame()).await?;
+
+  let mut consumers: HashMap<ChannelId, String> = HashMap::new();
+  let mut publishing: HashMap<ChannelId, ContentFrame> = HashMap::new();
+  let mut delivery_tag = 0;
+
+  loop {
+    let (channel, frame) = reader.next_frame().await?;
+
+    let reply = match frame {
+      Frame::ConnectionStartOk(_) => {
+        Some(ConnectionTune { chan_max: 0, frame_max: FRAME_MAX, heartbeat: 0 }.into_frame())
+      },
+      Frame::ConnectionOpen(_) => Some(ConnectionOpenOk { reserved1: "".into() }.into_frame()),
+      Frame::ConnectionClose(_) => {
+        writer.dispatch(0, ConnectionCloseOk {}.into_frame()).await?;
+        return Ok(());
+      },
+      Frame::ChannelOpen(_) => Some(ChannelOpenOk { reserved1: "".into() }.into_frame()),
+      Frame::ChannelClose(_) => Some(ChannelCloseOk {}.into_frame()),
+      Frame::ExchangeDeclare(_) => Some(ExchangeDeclareOk {}.into_frame()),
+      Frame::QueueDeclare(declare) => {
+        let name = if declare.name.0.is_empty() { format!("loopback-{}", channel) } else { declare.name.0 };
+        Some(QueueDeclareOk { name: name.into(), msg_count: 0, consumer_count: 0 }.into_frame())
+      },
+      Frame::QueueBind(_) => Some(QueueBindOk {}.into_frame()),
+      Frame::BasicConsume(consume) => {
+        let tag = if consume.tag.0.is_empty() { format!("ctag-{}", channel) } else { consume.tag.0 };
+        consumers.insert(channel, tag.clone());
+        Some(BasicConsumeOk { tag: tag.into() }.into_frame())
+      },
+      frame @ Frame::BasicPublish(_) => {
+        publishing.insert(channel, ContentFrame::WithMethod(frame));
+        None
+      },
+      Frame::ContentHeader(header) => {
+        let pending = publishing.remove(&channel).unwrap().with_content_header(header);
+        publishing.insert(channel, pending);
+        None
+      },
+      Frame::ContentBody(body) => {
+        let pending = publishing.remove(&channel).unwrap().with_body(body);
+        publishing.insert(channel, pending);
+        None
+      },
+      _ => None
+    };
+
+    if let Some(reply) = reply {
+      writer.dispatch(channel, reply).await?;
+    }
+
+    let completed = publishing.get(&channel).map(|pending| pending.is_complete()).unwrap_or(false);
+    if completed {
+      let (method, header, body) = publishing.remove(&channel).unwrap().into_parts().unwrap();
+
+      if let (Frame::BasicPublish(publish), Some(consumer_tag)) = (method, consumers.get(&channel)) {
+        delivery_tag += 1;
+        let deliver = delivery(channel, consumer_tag, delivery_tag, &publish.routing_key.0, header.prop_list, body);
+        writer.enqueue(deliver)?;
+        writer.flush().await?;
+      }
+    }
+  }
+}
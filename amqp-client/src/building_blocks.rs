@@ -1,6 +1,17 @@
+mod channel_dispatcher;
 mod channel_manager;
 mod macros;
 mod command;
+mod delivery_tracker;
+mod metrics;
+mod outgoing;
+mod rpc;
 
+pub(crate) use channel_dispatcher::{ChannelDispatcher, ConsumerHandoff};
 pub(crate) use channel_manager::ChannelManager;
 pub(crate) use command::{Command, CommandPayload};
+pub(crate) use delivery_tracker::DeliveryTracker;
+pub(crate) use metrics::{ChannelMetrics, ConnectionMetrics, FrameMetrics};
+pub(crate) use rpc::RpcSlot;
+pub(crate) use outgoing::{outgoing_queue, OutgoingRx, OutgoingTx};
+pub use outgoing::OutgoingMetrics;
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, info_span, warn, Span};
+use tokio::sync::mpsc::UnboundedSender;
+use crate::protocol::types::ChannelId;
+use crate::protocol::frame::{Frame, BasicReject, ContentFrame};
+use crate::protocol::compression::decode_body;
+use crate::protocol::message::{Message, MessageMetadata};
+use crate::api::channel::{ChannelError, Delivery};
+use crate::building_blocks::{DeliveryTracker, OutgoingTx, RpcSlot};
+
+// consume puts the queue and its receiver here under the tag it asks for before basic.consume goes out,
+// consume-ok moves them to the consumers; keyed so concurrent consumes on one channel can't swap receivers
+pub(crate) type ConsumerHandoff = Arc<Mutex<HashMap<String, (String, UnboundedSender<Delivery>)>>>;
+
+// what a channel task owns: the content being assembled, the consumers and the reply slot,
+// so a slow channel only holds up itself
+pub(crate) struct ChannelDispatcher {
+  id: ChannelId,
+  outgoing_tx: OutgoingTx,
+  tracker: Arc<DeliveryTracker>,
+  rpc: Arc<RpcSlot>,
+  handoff: ConsumerHandoff,
+  pending: Option<ContentFrame>,
+  consumers: HashMap<String, Consumer>,
+  max_decompressed_size: usize,
+  span: Span,
+}
+
+struct Consumer {
+  tag: Arc<str>,
+  queue: String,
+  consumer_tx: UnboundedSender<Delivery>,
+  // open as long as the consumer receives deliveries, parent of their spans
+  span: Span,
+}
+
+impl ChannelDispatcher {
+  pub fn new(
+    id: ChannelId,
+    outgoing_tx: OutgoingTx,
+    tracker: Arc<DeliveryTracker>,
+    rpc: Arc<RpcSlot>,
+    handoff: ConsumerHandoff,
+    max_decompressed_size: usize,
+    span: Span
+  ) -> Self {
+    Self { id, outgoing_tx, tracker, rpc, handoff, pending: None, consumers: Default::default(), max_decompressed_size, span }
+  }
+
+  // handles deliveries and replies, everything else is given back to the channel task
+  pub fn dispatch(&mut self, frame: Frame) -> Option<Frame> {
+    match frame {
+      Frame::BasicDeliver(..) => {
+        self.pending = Some(ContentFrame::WithMethod(frame));
+      },
+      Frame::ContentHeader(header) => {
+        let pending = self.pending.take()?.with_content_header(header);
+        self.complete(pending);
+      },
+      Frame::ContentBody(body) => {
+        let pending = self.pending.take()?.with_body(body);
+        self.complete(pending);
+      },
+      Frame::BasicConsumeOk(consume_ok) => {
+        let handoff = self.handoff.lock().unwrap().remove(&consume_ok.tag.0);
+        match handoff {
+          Some((queue, consumer_tx)) => {
+            let span = info_span!(parent: &self.span, "consumer", consumer_tag = %consume_ok.tag.0, queue = %queue);
+            info!(parent: &span, "consumer started");
+            let tag = consume_ok.tag.0.as_str().into();
+            self.consumers.insert(consume_ok.tag.0.clone(), Consumer { tag, queue, consumer_tx, span });
+          },
+          None => warn!("consume-ok for {} on channel {} without a consumer", consume_ok.tag.0, self.id)
+        }
+        self.rpc.resolve(consume_ok.into_frame());
+      },
+      Frame::ChannelOpenOk(..) |
+      Frame::ChannelCloseOk(..) |
+      Frame::ExchangeDeclareOk(..) |
+      Frame::QueueDeclareOk(..) |
+      Frame::QueueBindOk(..) |
+      Frame::QueueUnbindOk(..) |
+      Frame::ChannelFlowOk(..) |
+      Frame::TxSelectOk(..) |
+      Frame::TxCommitOk(..) |
+      Frame::TxRollbackOk(..) => {
+        self.rpc.resolve(frame);
+      },
+      Frame::BasicRecoverOk(..) => {
+        // deliveries before recover-ok belong to the old epoch, the ones after it to the new one
+        self.tracker.reset();
+        self.rpc.resolve(frame);
+      },
+      frame => return Some(frame)
+    }
+
+    None
+  }
+
+  fn complete(&mut self, pending: ContentFrame) {
+    if pending.is_complete() {
+      self.deliver(pending);
+    } else {
+      self.pending = Some(pending);
+    }
+  }
+
+  fn deliver(&mut self, frame: ContentFrame) {
+    let (frame, header, body) = match frame.into_parts() {
+      Some(parts) => parts,
+      None => return
+    };
+
+    let deliver = match frame {
+      Frame::BasicDeliver(deliver) => deliver,
+      frame => {
+        warn!("unexpected content method on channel {}: {:?}", self.id, frame);
+        return;
+      }
+    };
+
+    let delivery_tag = deliver.deliver_tag;
+    if let Some(err) = header.prop_error {
+      // a poison message shouldn't reach consumers, let the broker dead-letter it
+      warn!("Rejecting delivery {} with malformed properties: {}", delivery_tag, err);
+      self.reject(delivery_tag);
+      return;
+    }
+
+    let mut properties = header.prop_list;
+    let body = match decode_body(&mut properties, body, self.max_decompressed_size) {
+      Ok(body) => body,
+      Err(err) => {
+        warn!("Rejecting delivery {} which failed to decompress: {}", delivery_tag, err);
+        self.reject(delivery_tag);
+        return;
+      }
+    };
+
+    let consumer = match self.consumers.get(&deliver.consumer_tag.0) {
+      Some(consumer) => consumer,
+      None => {
+        warn!("delivery {} for unknown consumer {} on channel {}", delivery_tag, deliver.consumer_tag.0, self.id);
+        return;
+      }
+    };
+
+    // field names follow the OpenTelemetry messaging conventions, the level matches the publish span
+    // so the trace continues at the usual filters
+    let span = info_span!(
+      parent: &consumer.span,
+      "deliver",
+      otel.kind = "consumer",
+      otel.name = %format_args!("{} receive", consumer.queue),
+      messaging.system = "rabbitmq",
+      messaging.operation = "receive",
+      messaging.destination.name = %deliver.exchange.0,
+      messaging.rabbitmq.destination.routing_key = %deliver.routing_key.0,
+      messaging.source.name = %consumer.queue,
+      messaging.message.id = properties.message_id.as_deref(),
+      messaging.message.conversation_id = properties.correlation_id.as_deref(),
+      delivery_tag,
+      redelivered = deliver.redelivered
+    );
+    #[cfg(feature = "opentelemetry")]
+    crate::protocol::propagation::extract(&span, &properties);
+    let metadata = MessageMetadata::new(
+      delivery_tag,
+      deliver.redelivered,
+      deliver.exchange.0,
+      deliver.routing_key.0,
+      consumer.tag.clone()
+    );
+    let message = Message::new(self.id, self.outgoing_tx.clone(), self.tracker.clone(), properties, metadata, body, span);
+
+    if consumer.consumer_tx.send(Ok(message)).is_err() {
+      // the receiver was dropped, the message stays unacked until the channel closes
+      warn!(parent: &consumer.span, "consumer {} on channel {} is gone", deliver.consumer_tag.0, self.id);
+      self.consumers.remove(&deliver.consumer_tag.0);
+    }
+  }
+
+  // the channel task is ending, consumers get the reason before their receivers close
+  pub fn fail(&mut self, err: &ChannelError) {
+    for (_, consumer) in self.consumers.drain() {
+      let _ = consumer.consumer_tx.send(Err(err.clone()));
+    }
+  }
+
+  fn reject(&self, delivery_tag: i64) {
+    let method = BasicReject { delivery_tag, requeue: false };
+    let _ = self.outgoing_tx.send((self.id, method.into_frame()).into());
+  }
+}
+
+impl Drop for Consumer {
+  fn drop(&mut self) {
+    info!(parent: &self.span, "consumer stopped");
+  }
+}
+
+impl Drop for ChannelDispatcher {
+  // the channel task is gone, nothing would answer the call in flight
+  fn drop(&mut self) {
+    self.rpc.close();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::building_blocks::{outgoing_queue, ConnectionMetrics};
+  use crate::protocol::frame::{BasicRecoverOk, QueueDeclareOk};
+
+  fn dispatcher(tracker: Arc<DeliveryTracker>) -> ChannelDispatcher {
+    let (outgoing_tx, _) = outgoing_queue(0);
+    let rpc = Arc::new(RpcSlot::new(ConnectionMetrics::new("test").channel(1)));
+    ChannelDispatcher::new(1, outgoing_tx, tracker, rpc, Default::default(), usize::MAX, Span::none())
+  }
+
+  #[test]
+  fn keeps_deliveries_settleable_across_other_replies() {
+    let tracker = Arc::new(DeliveryTracker::new(ConnectionMetrics::new("test").channel(1)));
+    let mut dispatcher = dispatcher(tracker.clone());
+    let epoch = tracker.track(1, &"ctag".into());
+
+    let declare_ok = QueueDeclareOk { name: "queue".into(), msg_count: 0, consumer_count: 0 };
+    assert!(dispatcher.dispatch(declare_ok.into_frame()).is_none());
+
+    assert_eq!(tracker.unsettled_count(), 1);
+    assert_eq!(tracker.settle(epoch, 1, false), 1);
+  }
+
+  #[test]
+  fn invalidates_deliveries_on_recover_ok() {
+    let tracker = Arc::new(DeliveryTracker::new(ConnectionMetrics::new("test").channel(1)));
+    let mut dispatcher = dispatcher(tracker.clone());
+    let epoch = tracker.track(1, &"ctag".into());
+
+    assert!(dispatcher.dispatch(BasicRecoverOk {}.into_frame()).is_none());
+
+    assert_eq!(tracker.unsettled_count(), 0);
+    assert_eq!(tracker.settle(epoch, 1, false), 0);
+  }
+}
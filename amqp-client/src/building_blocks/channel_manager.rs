@@ -1,85 +1,55 @@
-use std::collections::{HashMap, VecDeque};
-use tokio::sync::{oneshot};
-use tokio::sync::mpsc::{UnboundedSender};
-use crate::protocol::types::{ChannelId};
-use crate::protocol::frame::{FrameEnvelope, Frame, ContentFrame};
-use crate::protocol::message::{Message, MessageMetadata};
+use std::collections::HashMap;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::protocol::types::ChannelId;
+use crate::protocol::frame::FrameEnvelope;
+use crate::building_blocks::ConnectionMetrics;
+use anyhow::bail;
 use crate::Result;
 
+// routes incoming frames to the channel tasks, which assemble content and dispatch it themselves
 pub (crate) struct ChannelManager {
-  sync_waiters: HashMap<ChannelId, VecDeque<oneshot::Sender<Frame>>>,
   channel_dispatchers: HashMap<ChannelId, UnboundedSender<FrameEnvelope>>,
-  consumers: HashMap<ChannelId, HashMap<String, UnboundedSender<Message>>>,
+  metrics: ConnectionMetrics,
 }
 
 impl ChannelManager {
-  pub fn new() -> Self {
-
+  pub fn new(metrics: ConnectionMetrics) -> Self {
     Self {
-      sync_waiters: Default::default(),
-      consumers: Default::default(),
-      channel_dispatchers: Default::default()
-    }
-  }
-
-  pub fn get_responder(&mut self, channel: ChannelId) -> oneshot::Sender<Frame> {
-    self.sync_waiters.get_mut(&channel).unwrap().pop_front().unwrap()
-  }
-
-  pub fn register_responder(&mut self, channel: ChannelId, responder: oneshot::Sender<Frame>) {
-    if self.sync_waiters.contains_key(&channel) {
-      let channel_waiters = self.sync_waiters.get_mut(&channel).unwrap();
-      channel_waiters.push_back(responder);
-    } else {
-      self.sync_waiters.insert(channel, VecDeque::from([responder]));
+      channel_dispatchers: Default::default(),
+      metrics,
     }
   }
 
   pub fn register_channel(&mut self, channel: ChannelId, incoming_tx: UnboundedSender<FrameEnvelope>) {
     self.channel_dispatchers.insert(channel, incoming_tx);
+    self.report_channels();
   }
 
-  pub fn register_consumer(&mut self, channel: ChannelId, tag: String, consumer_tx: UnboundedSender<Message>) {
-    if !self.consumers.contains_key(&channel) {
-      self.consumers.insert(channel, Default::default());
-    }
-
-    let channel_consumers = self.consumers.get_mut(&channel).unwrap();
-    channel_consumers.insert(tag, consumer_tx);
+  // stops routing frames to a closed channel, so its id can be handed out again
+  pub fn unregister_channel(&mut self, channel: ChannelId) {
+    self.channel_dispatchers.remove(&channel);
+    self.report_channels();
   }
 
-  pub fn dispatch_content_frame(&mut self, channel: ChannelId, outgoing_tx: UnboundedSender<FrameEnvelope>, frame: ContentFrame) {
-    if let ContentFrame::WithBody((frame, header, body)) = frame {
-      let channel_consumers = self.consumers.get_mut(&channel).unwrap();
-
-      match frame {
-        Frame::BasicDeliver(deliver) => {
-          let consumer = channel_consumers.get_mut(&deliver.consumer_tag.0).unwrap();
-          // todo: add metadata to the message
-          let metadata = MessageMetadata::new(
-            deliver.deliver_tag,
-            deliver.redelivered,
-            deliver.exchange.0,
-            deliver.routing_key.0
-          );
-
-          let message = Message::new(channel, outgoing_tx, header.prop_list, metadata, body.0);
-
-          consumer.send(message).unwrap();
-        },
-        _ => {
-          todo!("to be implemented")
-        }
-      }
-
-    } else {
-      panic!("Invalid frame variant")
-    }
+  // channel 0 belongs to the connection itself
+  fn report_channels(&self) {
+    let open = self.channel_dispatchers.keys().filter(|channel| **channel != 0).count();
+    self.metrics.channels_open(open);
   }
 
   pub fn dispatch_channel_frame(&self, frame: FrameEnvelope) -> Result<()> {
-    let dispatcher = self.channel_dispatchers.get(&frame.0).unwrap();
+    let dispatcher = match self.channel_dispatchers.get(&frame.0) {
+      Some(dispatcher) => dispatcher,
+      None => bail!("Unknown channel {}", frame.0)
+    };
     dispatcher.send(frame)?;
     Ok(())
   }
 }
+
+impl Drop for ChannelManager {
+  // the reader loop exited, no channel is left open
+  fn drop(&mut self) {
+    self.metrics.channels_open(0);
+  }
+}
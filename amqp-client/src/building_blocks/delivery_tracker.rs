@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::protocol::types::Long;
+use crate::building_blocks::ChannelMetrics;
+
+// delivery tags handed out on a channel which are not acked/rejected yet, with the consumer they went to;
+// basic.recover starts a new epoch and invalidates everything delivered before it
+#[derive(Debug)]
+pub(crate) struct DeliveryTracker {
+  epoch: AtomicU64,
+  unsettled: Mutex<BTreeMap<Long, Arc<str>>>,
+  metrics: ChannelMetrics,
+}
+
+impl DeliveryTracker {
+  pub fn new(metrics: ChannelMetrics) -> Self {
+    Self { epoch: Default::default(), unsettled: Default::default(), metrics }
+  }
+
+  pub fn metrics(&self) -> &ChannelMetrics {
+    &self.metrics
+  }
+
+  pub fn track(&self, delivery_tag: Long, consumer_tag: &Arc<str>) -> u64 {
+    self.unsettled.lock().unwrap().insert(delivery_tag, consumer_tag.clone());
+    self.metrics.delivered();
+    self.metrics.unacked(consumer_tag, 1.0);
+    self.epoch.load(Ordering::Acquire)
+  }
+
+  // returns how many deliveries were settled, zero when the tag was already settled or belongs to an older epoch
+  pub fn settle(&self, epoch: u64, delivery_tag: Long, multiple: bool) -> usize {
+    if epoch != self.epoch.load(Ordering::Acquire) {
+      return 0;
+    }
+
+    let mut unsettled = self.unsettled.lock().unwrap();
+
+    if !unsettled.contains_key(&delivery_tag) {
+      return 0;
+    }
+
+    let settled = if multiple {
+      let pending = unsettled.split_off(&(delivery_tag + 1));
+      std::mem::replace(&mut *unsettled, pending)
+    } else {
+      unsettled.remove_entry(&delivery_tag).into_iter().collect()
+    };
+    drop(unsettled);
+
+    self.report_settled(&settled);
+    settled.len()
+  }
+
+  pub fn reset(&self) {
+    let mut unsettled = self.unsettled.lock().unwrap();
+    self.epoch.fetch_add(1, Ordering::AcqRel);
+    let settled = std::mem::take(&mut *unsettled);
+    drop(unsettled);
+
+    self.report_settled(&settled);
+  }
+
+  pub fn unsettled_count(&self) -> usize {
+    self.unsettled.lock().unwrap().len()
+  }
+
+  fn report_settled(&self, settled: &BTreeMap<Long, Arc<str>>) {
+    for consumer_tag in settled.values() {
+      self.metrics.unacked(consumer_tag, -1.0);
+    }
+  }
+}
+
+impl Drop for DeliveryTracker {
+  // the channel and its messages are gone, the server requeues whatever they left unacked
+  fn drop(&mut self) {
+    let unsettled = std::mem::take(self.unsettled.get_mut().unwrap());
+    self.report_settled(&unsettled);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::building_blocks::ConnectionMetrics;
+
+  fn tracker() -> DeliveryTracker {
+    DeliveryTracker::new(ConnectionMetrics::new("test").channel(1))
+  }
+
+  #[test]
+  fn settles_a_delivery_once() {
+    let tracker = tracker();
+    let consumer: Arc<str> = "ctag".into();
+    let epoch = tracker.track(1, &consumer);
+
+    assert_eq!(tracker.settle(epoch, 1, false), 1);
+    assert_eq!(tracker.settle(epoch, 1, false), 0);
+    assert_eq!(tracker.unsettled_count(), 0);
+  }
+
+  #[test]
+  fn multiple_settles_everything_up_to_the_tag() {
+    let tracker = tracker();
+    let consumer: Arc<str> = "ctag".into();
+    let epoch = tracker.track(1, &consumer);
+    tracker.track(2, &consumer);
+    tracker.track(3, &consumer);
+
+    assert_eq!(tracker.settle(epoch, 2, true), 2);
+    assert_eq!(tracker.unsettled_count(), 1);
+    assert_eq!(tracker.settle(epoch, 1, false), 0);
+    assert_eq!(tracker.settle(epoch, 3, false), 1);
+  }
+
+  #[test]
+  fn reset_invalidates_earlier_deliveries() {
+    let tracker = tracker();
+    let consumer: Arc<str> = "ctag".into();
+    let old_epoch = tracker.track(1, &consumer);
+
+    tracker.reset();
+    let new_epoch = tracker.track(1, &consumer);
+
+    assert_ne!(old_epoch, new_epoch);
+    assert_eq!(tracker.settle(old_epoch, 1, false), 0);
+    assert_eq!(tracker.settle(new_epoch, 1, false), 1);
+  }
+}
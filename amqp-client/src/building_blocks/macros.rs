@@ -50,12 +50,17 @@ macro_rules! generate_protocol_methods {
 
             pub fn to_raw_repr(self) -> Vec<u8> {
               let mut buf = vec![];
-              buf.write_short($class_id).unwrap();
-              buf.write_short($method_id).unwrap();
+              self.write_raw_repr(&mut buf).unwrap();
+              buf
+            }
+
+            pub fn write_raw_repr<W: std::io::Write>(self, buf: &mut W) -> Result<()> {
+              buf.write_short($class_id)?;
+              buf.write_short($method_id)?;
               $(
-                let $field = buf.[<write_ $type:lower >](self.$field).unwrap();
+                buf.[<write_ $type:lower >](self.$field)?;
               )*
-              buf
+              Ok(())
             }
 
             pub fn class_id(&self) -> Short {
@@ -82,7 +87,8 @@ macro_rules! generate_protocol_methods {
             [<$class $method>]([<$class $method>]),
           )+
         )+
-        ContentHeader(ContentHeader),
+        // boxed, the properties would make every frame as large as a header
+        ContentHeader(Box<ContentHeader>),
         ContentBody(ContentBody),
         Heartbeat
       }
@@ -111,6 +117,54 @@ macro_rules! generate_protocol_methods {
           }
         }
 
+        // e.g. "Basic.Publish", for logs and traces
+        pub fn name(&self) -> &'static str {
+          match self {
+            $(
+              $(
+                Frame::[<$class $method>](_) => concat!(stringify!($class), ".", stringify!($method)),
+              )+
+            )+
+            Frame::ContentHeader(_) => "ContentHeader",
+            Frame::ContentBody(_) => "ContentBody",
+            Frame::Heartbeat => "Heartbeat"
+          }
+        }
+
+        // class and method id, none for content and heartbeat frames
+        pub fn method_ids(&self) -> Option<(Short, Short)> {
+          match self {
+            $(
+              $(
+                Frame::[<$class $method>](_) => Some(($class_id, $method_id)),
+              )+
+            )+
+            _ => None
+          }
+        }
+
+        pub fn write_raw_repr<W: std::io::Write>(self, buf: &mut W) -> Result<()> {
+          match self {
+            $(
+              $(
+                Frame::[<$class $method>](payload) => {
+                  payload.write_raw_repr(buf)
+                }
+              )+
+            )+,
+            Frame::ContentHeader(header) => {
+              header.write_raw_repr(buf)
+            },
+            Frame::ContentBody(body) => {
+              buf.write_all(&body.0)?;
+              Ok(())
+            },
+            Frame::Heartbeat => {
+              Ok(())
+            }
+          }
+        }
+
         pub fn to_raw_repr(self) -> Vec<u8> {
           match self {
             $(
@@ -150,21 +204,3 @@ macro_rules! invoke_command_async {
   }
 }
 
-// todo: rewrite as function?
-#[macro_export]
-macro_rules! invoke_sync_method {
-  (
-    $channel:expr,
-    $command_tx:expr,
-    $outgoing_tx:expr,
-    $payload:expr
-  ) => {
-    {
-      let (responder_tx, responder_rx) = oneshot::channel::<Frame>();
-      invoke_command_async!($command_tx, CommandPayload::RegisterResponder(($channel, responder_tx)));
-
-      $outgoing_tx.send(($channel, $payload)).unwrap();
-      responder_rx
-    }
-  }
-}
@@ -0,0 +1,189 @@
+#[cfg(feature = "metrics")]
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "metrics")]
+use ::metrics::{counter, gauge, histogram, Counter};
+use crate::protocol::types::ChannelId;
+use self::names::*;
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+mod names {
+  pub const FRAMES_RECEIVED: &str = "amqp_client_frames_received_total";
+  pub const BYTES_RECEIVED: &str = "amqp_client_bytes_received_total";
+  pub const FRAMES_SENT: &str = "amqp_client_frames_sent_total";
+  pub const BYTES_SENT: &str = "amqp_client_bytes_sent_total";
+  pub const CHANNELS_OPEN: &str = "amqp_client_channels_open";
+  pub const HEARTBEATS_MISSED: &str = "amqp_client_heartbeats_missed_total";
+  pub const PUBLISHED: &str = "amqp_client_published_total";
+  pub const PUBLISHED_BYTES: &str = "amqp_client_published_bytes_total";
+  pub const DELIVERED: &str = "amqp_client_delivered_total";
+  pub const ACKED: &str = "amqp_client_acked_total";
+  pub const REJECTED: &str = "amqp_client_rejected_total";
+  pub const UNACKED: &str = "amqp_client_unacked_messages";
+  pub const RPC_DURATION: &str = "amqp_client_rpc_duration_seconds";
+}
+
+// records through the `metrics` facade when the feature is on, every call is a no-op otherwise;
+// everything is labeled with the connection name, per channel metrics with the channel id too.
+// Counter handles are resolved once and kept, so the recorder has to be installed before connecting
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionMetrics {
+  connection: Arc<str>,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl ConnectionMetrics {
+  pub fn new(connection: impl Into<Arc<str>>) -> Self {
+    Self { connection: connection.into() }
+  }
+
+  pub fn channel(&self, channel: ChannelId) -> ChannelMetrics {
+    let channel: Arc<str> = channel.to_string().into();
+    #[cfg(feature = "metrics")]
+    let labels = [("connection", self.connection.clone()), ("channel", channel.clone())];
+
+    ChannelMetrics {
+      #[cfg(feature = "metrics")]
+      published: counter!(PUBLISHED, &labels),
+      #[cfg(feature = "metrics")]
+      published_bytes: counter!(PUBLISHED_BYTES, &labels),
+      #[cfg(feature = "metrics")]
+      delivered: counter!(DELIVERED, &labels),
+      #[cfg(feature = "metrics")]
+      acked: counter!(ACKED, &labels),
+      #[cfg(feature = "metrics")]
+      rejected: counter!(REJECTED, &labels),
+      connection: self.connection.clone(),
+      channel,
+    }
+  }
+
+  pub fn frames_received(&self) -> FrameMetrics {
+    FrameMetrics::new(self, (FRAMES_RECEIVED, BYTES_RECEIVED))
+  }
+
+  pub fn frames_sent(&self) -> FrameMetrics {
+    FrameMetrics::new(self, (FRAMES_SENT, BYTES_SENT))
+  }
+
+  pub fn channels_open(&self, count: usize) {
+    #[cfg(feature = "metrics")]
+    gauge!(CHANNELS_OPEN, "connection" => self.connection.clone()).set(count as f64);
+  }
+
+  pub fn heartbeat_missed(&self) {
+    #[cfg(feature = "metrics")]
+    counter!(HEARTBEATS_MISSED, "connection" => self.connection.clone()).increment(1);
+  }
+}
+
+// the frame and byte counters of one direction, owned by the reader or the writer;
+// the channel label is only formatted the first time a channel shows up
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) struct FrameMetrics {
+  connection: Arc<str>,
+  names: (&'static str, &'static str),
+  #[cfg(feature = "metrics")]
+  counters: HashMap<ChannelId, (Counter, Counter)>,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl FrameMetrics {
+  fn new(metrics: &ConnectionMetrics, names: (&'static str, &'static str)) -> Self {
+    Self {
+      connection: metrics.connection.clone(),
+      names,
+      #[cfg(feature = "metrics")]
+      counters: HashMap::new(),
+    }
+  }
+
+  pub fn frame(&mut self, channel: ChannelId, bytes: usize) {
+    #[cfg(feature = "metrics")]
+    {
+      let (frames, bytes_total) = self.counters.entry(channel).or_insert_with(|| {
+        let labels = [("connection", self.connection.clone()), ("channel", channel.to_string().into())];
+        (counter!(self.names.0, &labels), counter!(self.names.1, &labels))
+      });
+      frames.increment(1);
+      bytes_total.increment(bytes as u64);
+    }
+  }
+}
+
+#[derive(Clone)]
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) struct ChannelMetrics {
+  connection: Arc<str>,
+  channel: Arc<str>,
+  #[cfg(feature = "metrics")]
+  published: Counter,
+  #[cfg(feature = "metrics")]
+  published_bytes: Counter,
+  #[cfg(feature = "metrics")]
+  delivered: Counter,
+  #[cfg(feature = "metrics")]
+  acked: Counter,
+  #[cfg(feature = "metrics")]
+  rejected: Counter,
+}
+
+// the counter handles have no Debug of their own
+impl Debug for ChannelMetrics {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ChannelMetrics")
+      .field("connection", &self.connection)
+      .field("channel", &self.channel)
+      .finish()
+  }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl ChannelMetrics {
+  pub fn published(&self, body_len: usize) {
+    #[cfg(feature = "metrics")]
+    {
+      self.published.increment(1);
+      self.published_bytes.increment(body_len as u64);
+    }
+  }
+
+  pub fn delivered(&self) {
+    #[cfg(feature = "metrics")]
+    self.delivered.increment(1);
+  }
+
+  // `count` is more than one for a multiple ack
+  pub fn acked(&self, count: usize) {
+    #[cfg(feature = "metrics")]
+    self.acked.increment(count as u64);
+  }
+
+  pub fn rejected(&self, count: usize) {
+    #[cfg(feature = "metrics")]
+    self.rejected.increment(count as u64);
+  }
+
+  pub fn unacked(&self, consumer: &Arc<str>, delta: f64) {
+    #[cfg(feature = "metrics")]
+    gauge!(
+      UNACKED,
+      "connection" => self.connection.clone(),
+      "channel" => self.channel.clone(),
+      "consumer" => consumer.clone()
+    ).increment(delta);
+  }
+
+  // from the request being queued until its reply arrived
+  pub fn rpc_completed(&self, method: &'static str, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    histogram!(
+      RPC_DURATION,
+      "connection" => self.connection.clone(),
+      "channel" => self.channel.clone(),
+      "method" => method
+    ).record(elapsed);
+  }
+}
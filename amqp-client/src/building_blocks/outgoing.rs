@@ -0,0 +1,166 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Semaphore;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use anyhow::bail;
+use tokio::sync::mpsc::error::TryRecvError;
+use crate::protocol::frame::OutgoingFrame;
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutgoingMetrics {
+  // bytes of message bodies published and not written to the socket yet
+  pub queued_bytes: usize,
+  pub queued_frames: usize,
+  // zero when the queue is unbounded
+  pub capacity: usize,
+}
+
+struct QueuedFrame {
+  frame: OutgoingFrame,
+  bytes: usize,
+  permits: usize,
+}
+
+struct OutgoingQueue {
+  room: Semaphore,
+  capacity: usize,
+  queued_bytes: AtomicUsize,
+  queued_frames: AtomicUsize,
+}
+
+// the way from the channels to the writer task; protocol frames go through right away,
+// published content waits for room once `capacity` body bytes are queued
+pub(crate) fn outgoing_queue(capacity: usize) -> (OutgoingTx, OutgoingRx) {
+  let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+  let capacity = capacity.min(Semaphore::MAX_PERMITS).min(u32::MAX as usize);
+  let queue = Arc::new(OutgoingQueue {
+    room: Semaphore::new(capacity),
+    capacity,
+    queued_bytes: AtomicUsize::new(0),
+    queued_frames: AtomicUsize::new(0),
+  });
+
+  (
+    OutgoingTx { frames_tx, queue: queue.clone() },
+    OutgoingRx { frames_rx, queue, in_flight_bytes: 0, in_flight_frames: 0, in_flight_permits: 0 }
+  )
+}
+
+#[derive(Clone)]
+pub(crate) struct OutgoingTx {
+  frames_tx: UnboundedSender<QueuedFrame>,
+  queue: Arc<OutgoingQueue>,
+}
+
+impl OutgoingTx {
+  // never waits, replies and acks must not be held up behind published messages
+  pub fn send(&self, frame: OutgoingFrame) -> Result<()> {
+    self.push(QueuedFrame { bytes: body_len(&frame), frame, permits: 0 })
+  }
+
+  // waits until the writer has room for the body, a body larger than the whole queue waits for it to drain
+  pub async fn send_content(&self, frame: OutgoingFrame) -> Result<()> {
+    let bytes = body_len(&frame);
+
+    if self.queue.capacity == 0 {
+      return self.push(QueuedFrame { frame, bytes, permits: 0 });
+    }
+
+    let permits = bytes.min(self.queue.capacity);
+    match self.queue.room.acquire_many(permits as u32).await {
+      Ok(permit) => permit.forget(),
+      // the writer is gone
+      Err(_) => bail!("Failed to send. Connection closed")
+    }
+
+    self.push(QueuedFrame { frame, bytes, permits })
+  }
+
+  fn push(&self, queued: QueuedFrame) -> Result<()> {
+    let (bytes, permits) = (queued.bytes, queued.permits);
+    self.queue.queued_bytes.fetch_add(bytes, Ordering::AcqRel);
+    self.queue.queued_frames.fetch_add(1, Ordering::AcqRel);
+
+    if self.frames_tx.send(queued).is_err() {
+      self.queue.release(bytes, 1, permits);
+      bail!("Failed to send. Connection closed")
+    }
+
+    Ok(())
+  }
+
+  pub fn metrics(&self) -> OutgoingMetrics {
+    OutgoingMetrics {
+      queued_bytes: self.queue.queued_bytes.load(Ordering::Acquire),
+      queued_frames: self.queue.queued_frames.load(Ordering::Acquire),
+      capacity: self.queue.capacity,
+    }
+  }
+}
+
+impl Debug for OutgoingTx {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("OutgoingTx").field("metrics", &self.metrics()).finish()
+  }
+}
+
+// frames handed to the writer still count as queued until `written` is called after the flush
+pub(crate) struct OutgoingRx {
+  frames_rx: UnboundedReceiver<QueuedFrame>,
+  queue: Arc<OutgoingQueue>,
+  in_flight_bytes: usize,
+  in_flight_frames: usize,
+  in_flight_permits: usize,
+}
+
+impl OutgoingRx {
+  pub async fn recv(&mut self) -> Option<OutgoingFrame> {
+    let queued = self.frames_rx.recv().await?;
+    Some(self.take(queued))
+  }
+
+  pub fn try_recv(&mut self) -> Result<OutgoingFrame, TryRecvError> {
+    let queued = self.frames_rx.try_recv()?;
+    Ok(self.take(queued))
+  }
+
+  fn take(&mut self, queued: QueuedFrame) -> OutgoingFrame {
+    self.in_flight_bytes += queued.bytes;
+    self.in_flight_frames += 1;
+    self.in_flight_permits += queued.permits;
+    queued.frame
+  }
+
+  pub fn written(&mut self) {
+    self.queue.release(self.in_flight_bytes, self.in_flight_frames, self.in_flight_permits);
+    self.in_flight_bytes = 0;
+    self.in_flight_frames = 0;
+    self.in_flight_permits = 0;
+  }
+}
+
+impl Drop for OutgoingRx {
+  // publishers waiting for room fail instead of hanging once the writer stops
+  fn drop(&mut self) {
+    self.queue.room.close();
+  }
+}
+
+impl OutgoingQueue {
+  fn release(&self, bytes: usize, frames: usize, permits: usize) {
+    self.queued_bytes.fetch_sub(bytes, Ordering::AcqRel);
+    self.queued_frames.fetch_sub(frames, Ordering::AcqRel);
+    if permits > 0 {
+      self.room.add_permits(permits);
+    }
+  }
+}
+
+fn body_len(frame: &OutgoingFrame) -> usize {
+  match frame {
+    OutgoingFrame::Content(_, _, _, body) => body.len(),
+    OutgoingFrame::Single(_) => 0
+  }
+}
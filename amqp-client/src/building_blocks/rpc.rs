@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+use anyhow::bail;
+use tracing::warn;
+use tokio::sync::{oneshot, Notify};
+use tokio::time::Instant;
+use crate::protocol::frame::Frame;
+use crate::protocol::types::{ChannelId, Short};
+use crate::building_blocks::{ChannelMetrics, OutgoingTx};
+use crate::Result;
+
+struct Waiter {
+  reply: (Short, Short),
+  // none for a detached request, its reply is only consumed
+  responder: Option<oneshot::Sender<Frame>>,
+}
+
+// replies come back in the order the requests were queued
+#[derive(Default)]
+struct SlotState {
+  waiters: VecDeque<Waiter>,
+  closed: bool,
+}
+
+// the synchronous method a channel has in flight, shared by the channel handles and the reader loop,
+// so a call registers its responder without going through the reader task
+pub(crate) struct RpcSlot {
+  // held for the whole call, the spec allows one outstanding synchronous method per channel
+  call: tokio::sync::Mutex<()>,
+  state: Mutex<SlotState>,
+  resolved: Notify,
+  metrics: ChannelMetrics,
+}
+
+impl RpcSlot {
+  pub fn new(metrics: ChannelMetrics) -> Self {
+    Self {
+      call: Default::default(),
+      state: Default::default(),
+      resolved: Notify::new(),
+      metrics
+    }
+  }
+
+  pub async fn call(&self, channel: ChannelId, outgoing_tx: &OutgoingTx, frame: Frame) -> Result<Frame> {
+    let reply = Self::reply_to(&frame)?;
+
+    let _call = self.call.lock().await;
+    let (responder, response) = oneshot::channel();
+    let waiter = Waiter { reply, responder: Some(responder) };
+    let method = frame.name();
+
+    // a call dropped by its caller, or a detached request, keeps the slot until the server answers it
+    let started = loop {
+      let resolved = self.resolved.notified();
+      {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+          bail!("Channel {} is closed", channel);
+        }
+        if state.waiters.is_empty() {
+          Self::send(&mut state, channel, outgoing_tx, waiter, frame)?;
+          break Instant::now();
+        }
+      }
+      resolved.await;
+    };
+
+    match response.await {
+      Ok(frame) => {
+        self.metrics.rpc_completed(method, started.elapsed());
+        Ok(frame)
+      },
+      Err(_) => bail!("Channel {} closed before the reply arrived", channel)
+    }
+  }
+
+  // queues the request right away, ahead of anything the caller sends next, without waiting for the reply;
+  // used where nothing can be awaited, e.g. when a transaction guard is dropped
+  pub fn call_detached(&self, channel: ChannelId, outgoing_tx: &OutgoingTx, frame: Frame) -> Result<()> {
+    let reply = Self::reply_to(&frame)?;
+
+    let mut state = self.state.lock().unwrap();
+    if state.closed {
+      bail!("Channel {} is closed", channel);
+    }
+    Self::send(&mut state, channel, outgoing_tx, Waiter { reply, responder: None }, frame)
+  }
+
+  fn reply_to(frame: &Frame) -> Result<(Short, Short)> {
+    match frame.method_ids() {
+      // every synchronous request is answered by the next method of its class
+      Some((class_id, method_id)) => Ok((class_id, method_id + 1)),
+      None => bail!("Not a method frame: {:?}", frame)
+    }
+  }
+
+  // sent under the state lock, so the waiters stay in the order of their requests
+  fn send(state: &mut SlotState, channel: ChannelId, outgoing_tx: &OutgoingTx, waiter: Waiter, frame: Frame) -> Result<()> {
+    outgoing_tx.send((channel, frame).into())?;
+    state.waiters.push_back(waiter);
+    Ok(())
+  }
+
+  // a reply nobody waits for, or not the one expected, is logged and dropped
+  pub fn resolve(&self, frame: Frame) {
+    let mut state = self.state.lock().unwrap();
+
+    let waiter = match state.waiters.front() {
+      Some(waiter) if frame.method_ids() == Some(waiter.reply) => state.waiters.pop_front(),
+      waiter => {
+        warn!("unexpected reply {:?}, waiting for {:?}", frame.method_ids(), waiter.map(|waiter| waiter.reply));
+        return;
+      }
+    };
+
+    drop(state);
+    if let Some(responder) = waiter.and_then(|waiter| waiter.responder) {
+      let _ = responder.send(frame);
+    }
+    self.resolved.notify_waiters();
+  }
+
+  // fails the calls in flight and every later one
+  pub fn close(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.closed = true;
+    state.waiters.clear();
+    drop(state);
+    self.resolved.notify_waiters();
+  }
+}
+
+impl Debug for RpcSlot {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let state = self.state.lock().unwrap();
+    f.debug_struct("RpcSlot")
+      .field("waiting_for", &state.waiters.iter().map(|waiter| waiter.reply).collect::<Vec<_>>())
+      .field("closed", &state.closed)
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use super::*;
+  use crate::building_blocks::{outgoing_queue, ConnectionMetrics};
+  use crate::protocol::frame::{OutgoingFrame, TxRollback, TxRollbackOk};
+
+  #[tokio::test]
+  async fn detached_reply_does_not_resolve_the_next_call() {
+    let rpc = Arc::new(RpcSlot::new(ConnectionMetrics::new("test").channel(1)));
+    let (outgoing_tx, mut outgoing_rx) = outgoing_queue(0);
+
+    rpc.call_detached(1, &outgoing_tx, TxRollback {}.into_frame()).unwrap();
+    assert!(matches!(outgoing_rx.try_recv(), Ok(OutgoingFrame::Single((1, Frame::TxRollback(..))))));
+
+    let call = tokio::spawn({
+      let rpc = rpc.clone();
+      async move { rpc.call(1, &outgoing_tx, TxRollback {}.into_frame()).await }
+    });
+    tokio::task::yield_now().await;
+    // the call waits for the detached request to be answered before it is sent
+    assert!(outgoing_rx.try_recv().is_err());
+
+    rpc.resolve(TxRollbackOk {}.into_frame());
+    assert!(matches!(outgoing_rx.recv().await, Some(OutgoingFrame::Single((1, Frame::TxRollback(..))))));
+    assert!(!call.is_finished());
+
+    rpc.resolve(TxRollbackOk {}.into_frame());
+    assert!(matches!(call.await.unwrap().unwrap(), Frame::TxRollbackOk(..)));
+  }
+}
@@ -3,7 +3,21 @@ pub(crate) mod utils;
 pub(crate) mod default_channel;
 pub(crate) mod api;
 pub(crate) mod building_blocks;
-pub use crate::api::connection::{Connection, ConnectionFactory};
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+pub use crate::api::connection::{Connection, ConnectionFactory, ConnectionError, ConnectionState, Capabilities, ServerInfo,
+                                 BlockedState, ConnectionEvent, ConnectionEvents};
+pub use crate::building_blocks::OutgoingMetrics;
+pub use crate::api::connection::options::{ConnectionArgs, ConnectionAddress};
+pub use crate::api::connection::sasl::{SaslMechanism, Plain, AmqPlain, External};
+pub use crate::api::connection::credentials::{Credentials, CredentialsProvider, CredentialsFuture};
 pub use anyhow::{Result,Error,bail};
 pub use crate ::api::exchange::ExchangeType;
-pub use crate::protocol::message::{Message, MessageProperties};
+pub use crate::api::transaction::Transaction;
+pub use crate::api::channel::{AmqChannel, ChannelError, ChannelState, Delivery};
+pub use crate::protocol::message::{Message, MessageProperties, MessagePropertiesBuilder, MessageDeliveryMode, PropertiesError};
+pub use crate::protocol::types::{PropTable, Property, ShortStr, LongStr};
+pub use crate::protocol::compression::{Compression, CompressionOpts};
+#[cfg(feature = "serde")]
+pub use crate::protocol::serialization::{from_table, to_table};
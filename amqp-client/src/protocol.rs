@@ -4,3 +4,8 @@ pub(crate) mod types;
 pub(crate) mod frame;
 pub(crate) mod message;
 pub(crate) mod net;
+pub(crate) mod compression;
+#[cfg(feature = "serde")]
+pub(crate) mod serialization;
+#[cfg(feature = "opentelemetry")]
+pub(crate) mod propagation;
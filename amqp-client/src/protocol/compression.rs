@@ -0,0 +1,147 @@
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+use std::io::{Read, Write};
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+use anyhow::bail;
+use bytes::Bytes;
+use crate::protocol::message::MessageProperties;
+use crate::Result;
+
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+  #[cfg(feature = "gzip")]
+  Gzip,
+  #[cfg(feature = "zstd")]
+  Zstd,
+  #[cfg(feature = "lz4")]
+  Lz4,
+}
+
+impl Compression {
+  // value of the content-encoding property
+  pub fn encoding(&self) -> &'static str {
+    match *self {
+      #[cfg(feature = "gzip")]
+      Compression::Gzip => "gzip",
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => "zstd",
+      #[cfg(feature = "lz4")]
+      Compression::Lz4 => "lz4",
+    }
+  }
+
+  pub fn from_encoding(encoding: &str) -> Option<Self> {
+    match encoding {
+      #[cfg(feature = "gzip")]
+      "gzip" => Some(Compression::Gzip),
+      #[cfg(feature = "zstd")]
+      "zstd" => Some(Compression::Zstd),
+      #[cfg(feature = "lz4")]
+      "lz4" => Some(Compression::Lz4),
+      _ => None
+    }
+  }
+
+  #[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "lz4")), allow(unused_variables))]
+  pub fn compress(&self, body: &[u8]) -> Result<Vec<u8>> {
+    match *self {
+      #[cfg(feature = "gzip")]
+      Compression::Gzip => {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(body)?;
+        Ok(encoder.finish()?)
+      },
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => {
+        Ok(zstd::encode_all(body, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+      },
+      #[cfg(feature = "lz4")]
+      Compression::Lz4 => {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+        encoder.write_all(body)?;
+        Ok(encoder.finish()?)
+      },
+    }
+  }
+
+  // fails once the output grows past `max_size`, a few kilobytes on the wire can inflate to gigabytes
+  #[cfg_attr(not(any(feature = "gzip", feature = "zstd", feature = "lz4")), allow(unused_variables))]
+  pub fn decompress(&self, body: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    match *self {
+      #[cfg(feature = "gzip")]
+      Compression::Gzip => read_limited(flate2::read::GzDecoder::new(body), max_size),
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => read_limited(zstd::stream::read::Decoder::new(body)?, max_size),
+      #[cfg(feature = "lz4")]
+      Compression::Lz4 => read_limited(lz4_flex::frame::FrameDecoder::new(body), max_size),
+    }
+  }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "lz4"))]
+fn read_limited(decoder: impl Read, max_size: usize) -> Result<Vec<u8>> {
+  let mut decoded = vec![];
+  // one byte past the limit tells a body of exactly `max_size` from a larger one
+  decoder.take(max_size as u64 + 1).read_to_end(&mut decoded)?;
+  if decoded.len() > max_size {
+    bail!("Decompressed body exceeds {} bytes", max_size);
+  }
+  Ok(decoded)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionOpts {
+  pub codec: Compression,
+  // bodies smaller than this are sent as is
+  pub threshold: usize,
+}
+
+impl CompressionOpts {
+  pub fn new(codec: Compression) -> Self {
+    Self { codec, threshold: DEFAULT_COMPRESSION_THRESHOLD }
+  }
+}
+
+// bodies which already carry a content encoding are left to the caller
+pub(crate) fn encode_body(opts: Option<&CompressionOpts>, properties: &mut MessageProperties, body: Bytes) -> Result<Bytes> {
+  match opts {
+    Some(opts) if body.len() >= opts.threshold && properties.content_encoding.is_none() => {
+      properties.content_encoding = Some(opts.codec.encoding().into());
+      Ok(opts.codec.compress(&body)?.into())
+    },
+    _ => Ok(body)
+  }
+}
+
+// encodings this build doesn't know are delivered untouched
+pub(crate) fn decode_body(properties: &mut MessageProperties, body: Bytes, max_size: usize) -> Result<Bytes> {
+  let codec = properties.content_encoding.as_deref().and_then(Compression::from_encoding);
+
+  match codec {
+    Some(codec) => {
+      let decoded = codec.decompress(&body, max_size)?;
+      properties.content_encoding = None;
+      Ok(decoded.into())
+    },
+    None => Ok(body)
+  }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decompresses_up_to_the_limit() {
+    let body = Compression::Gzip.compress(&[0; 1024]).unwrap();
+    assert_eq!(Compression::Gzip.decompress(&body, 1024).unwrap().len(), 1024);
+  }
+
+  #[test]
+  fn rejects_bodies_past_the_limit() {
+    let body = Compression::Gzip.compress(&[0; 1024 * 1024]).unwrap();
+    assert!(Compression::Gzip.decompress(&body, 1024).is_err());
+  }
+}
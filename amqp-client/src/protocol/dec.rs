@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 use std::io::{Cursor};
 use byteorder::{BigEndian, ReadBytesExt};
-use log::{debug};
-use crate::protocol::types::{LongStr, Property, ShortStr};
+use anyhow::bail;
+use tracing::{debug};
+use crate::protocol::types::{LongBytes, LongStr, Property, ShortStr};
 use crate::{Result};
 
 pub trait Decode {
@@ -18,15 +19,17 @@ pub trait Decode {
   fn read_double(&mut self) -> Result<f64>;
   fn read_shortstr(&mut self) -> Result<ShortStr>;
   fn read_longstr(&mut self) -> Result<LongStr>;
+  fn read_longbytes(&mut self) -> Result<LongBytes>;
   fn read_field_value_pair(&mut self) -> Result<(ShortStr, Property)>;
   fn read_field_value(&mut self) -> Result<Property>;
   fn read_field_value_type(&mut self, ch: char) -> Result<Property>;
+  fn read_array(&mut self) -> Result<Vec<Property>>;
   fn read_proptable(&mut self) -> Result<HashMap<ShortStr, Property>>;
 }
 
 impl <T: std::io::Read + ?Sized> Decode for T {
   fn read_bool(&mut self) -> Result<bool> {
-    Ok(self.read_u8()? == 0)
+    Ok(self.read_u8()? != 0)
   }
   fn read_byte(&mut self) -> Result<u8> {
     Ok(self.read_u8()?)
@@ -78,6 +81,13 @@ impl <T: std::io::Read + ?Sized> Decode for T {
     Ok(LongStr(String::from_utf8(buff)?))
   }
 
+  fn read_longbytes(&mut self) -> Result<LongBytes> {
+    let size = Decode::read_uint(self)?;
+    let mut buff = vec![0_u8; size as usize];
+    self.read_exact(&mut buff)?;
+    Ok(LongBytes(buff))
+  }
+
   fn read_field_value_pair(&mut self) -> Result<(ShortStr, Property)> {
     let key = self.read_shortstr()?;
     let value = self.read_field_value()?;
@@ -103,15 +113,39 @@ impl <T: std::io::Read + ?Sized> Decode for T {
       'd' => Property::Double(self.read_double()?),
       's' => Property::ShortStr(self.read_shortstr()?),
       'S' => Property::LongStr(self.read_longstr()?),
+      'D' => Property::Decimal(self.read_byte()?, Decode::read_uint(self)?),
+      'T' => Property::Timestamp(self.read_ulong()?),
+      'A' => Property::Array(Decode::read_array(self)?),
+      'x' => {
+        let size = Decode::read_uint(self)?;
+        let mut buff = vec![0_u8; size as usize];
+        self.read_exact(&mut buff)?;
+        Property::ByteArray(buff)
+      },
+      'V' => Property::Void,
       'F' => Property::Table(self.read_proptable()?),
       _ => {
-        panic!("Unexpected values provided: {}", ch);
+        bail!("Unexpected field value type: {}", ch);
       }
     };
 
     Ok(value)
   }
 
+  fn read_array(&mut self) -> Result<Vec<Property>> {
+    let mut array = vec![];
+    let array_size = Decode::read_uint(self)?;
+    let mut buff = vec![0_u8; array_size as usize];
+    self.read_exact(&mut buff)?;
+    let mut cursor = Cursor::new(buff);
+
+    while cursor.position() < array_size as u64 {
+      array.push(cursor.read_field_value()?);
+    }
+
+    Ok(array)
+  }
+
   fn read_proptable(&mut self) -> Result<HashMap<ShortStr, Property>> {
     let mut table = HashMap::new();
     let table_size = Decode::read_uint(self)?;
@@ -120,7 +154,7 @@ impl <T: std::io::Read + ?Sized> Decode for T {
     self.read_exact(& mut buff)?;
     let mut cursor = Cursor::new(buff);
 
-    while cursor.position() < table_size as u64 - 1 {
+    while cursor.position() < table_size as u64 {
       let pair = cursor.read_field_value_pair()?;
       debug!("Table pair {:?}", &pair);
       table.insert(pair.0, pair.1);
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use byteorder::{BigEndian, WriteBytesExt};
-use crate::protocol::types::{LongStr, Property, ShortStr};
+use crate::protocol::types::{LongBytes, LongStr, Property, ShortStr};
+use anyhow::bail;
 use crate::{Result};
 
 pub trait Encode {
@@ -16,9 +17,11 @@ pub trait Encode {
   fn write_double(&mut self, val: f64) -> Result<()>;
   fn write_shortstr(&mut self, val: ShortStr) -> Result<()>;
   fn write_longstr(&mut self, val: LongStr) -> Result<()>;
+  fn write_longbytes(&mut self, val: LongBytes) -> Result<()>;
   fn write_field_value_pair(&mut self, val: (ShortStr, Property)) -> Result<()>;
   fn write_field_value(&mut self, val: Property) -> Result<()>;
   fn write_argument(&mut self, val: Property) -> Result<()>;
+  fn write_array(&mut self, val: Vec<Property>) -> Result<()>;
   fn write_proptable(&mut self, val: HashMap<ShortStr, Property>) -> Result<()>;
 }
 
@@ -75,9 +78,12 @@ impl <T: std::io::Write + ?Sized> Encode for T {
 
   fn write_shortstr(&mut self, val: ShortStr) -> Result<()> {
     let str_bytes = val.0.into_bytes();
-    // str_bytes.reverse();
+    // the length is a single byte, a longer string would corrupt the rest of the frame
+    if str_bytes.len() > u8::MAX as usize {
+      bail!("Short string of {} bytes exceeds 255", str_bytes.len())
+    }
     self.write_byte(str_bytes.len() as u8)?;
-    self.write(& str_bytes)?;
+    self.write_all(&str_bytes)?;
     Ok(())
   }
 
@@ -85,7 +91,13 @@ impl <T: std::io::Write + ?Sized> Encode for T {
     let str_bytes = val.0.into_bytes();
     // str_bytes.reverse();
     Encode::write_uint(self, str_bytes.len() as u32)?;
-    self.write(& str_bytes)?;
+    self.write_all(&str_bytes)?;
+    Ok(())
+  }
+
+  fn write_longbytes(&mut self, val: LongBytes) -> Result<()> {
+    Encode::write_uint(self, val.0.len() as u32)?;
+    self.write_all(&val.0)?;
     Ok(())
   }
 
@@ -145,6 +157,27 @@ impl <T: std::io::Write + ?Sized> Encode for T {
         self.write_byte('S' as u8)?;
         self.write_longstr(v)?;
       }
+      Property::Decimal(scale, v) => {
+        self.write_byte(b'D')?;
+        self.write_byte(scale)?;
+        Encode::write_uint(self, v)?;
+      }
+      Property::Timestamp(v) => {
+        self.write_byte(b'T')?;
+        self.write_ulong(v)?;
+      }
+      Property::Array(v) => {
+        self.write_byte(b'A')?;
+        self.write_array(v)?;
+      }
+      Property::ByteArray(v) => {
+        self.write_byte(b'x')?;
+        Encode::write_uint(self, v.len() as u32)?;
+        self.write_all(&v)?;
+      }
+      Property::Void => {
+        self.write_byte(b'V')?;
+      }
       Property::Table(v) => {
         self.write_byte('F' as u8)?;
         self.write_proptable(v)?;
@@ -192,6 +225,21 @@ impl <T: std::io::Write + ?Sized> Encode for T {
       Property::LongStr(v) => {
         self.write_longstr(v)?;
       }
+      Property::Decimal(scale, v) => {
+        self.write_byte(scale)?;
+        Encode::write_uint(self, v)?;
+      }
+      Property::Timestamp(v) => {
+        self.write_ulong(v)?;
+      }
+      Property::Array(v) => {
+        self.write_array(v)?;
+      }
+      Property::ByteArray(v) => {
+        Encode::write_uint(self, v.len() as u32)?;
+        self.write_all(&v)?;
+      }
+      Property::Void => {}
       Property::Table(v) => {
         self.write_proptable(v)?;
       }
@@ -199,6 +247,18 @@ impl <T: std::io::Write + ?Sized> Encode for T {
 
     Ok(())
   }
+  fn write_array(&mut self, val: Vec<Property>) -> Result<()> {
+    let mut buff = vec![];
+
+    for item in val {
+      buff.write_field_value(item)?;
+    }
+
+    Encode::write_uint(self, buff.len() as u32)?;
+    self.write_all(&buff)?;
+    Ok(())
+  }
+
   fn write_proptable(&mut self, val: HashMap<ShortStr, Property>) -> Result<()> {
     let mut buff = vec![];
 
@@ -207,7 +267,7 @@ impl <T: std::io::Write + ?Sized> Encode for T {
     }
 
     Encode::write_uint(self, buff.len() as u32)?;
-    self.write(& buff)?;
+    self.write_all(&buff)?;
     Ok(())
   }
 }
@@ -1,22 +1,29 @@
-use crate::{generate_protocol_methods};
+use crate::{generate_protocol_methods, Result};
 
+use bytes::{Bytes, BytesMut};
 use paste::paste;
 use crate::protocol::dec::Decode;
 use crate::protocol::enc::Encode;
-use crate::protocol::message::MessageProperties;
+use crate::protocol::message::{MessageProperties, PropertiesError};
 use crate::protocol::types::{Bool, ChannelId, Long};
-use super::types::{Byte, PropTable, LongStr, ShortStr, Short, Int, ULong};
+use super::types::{Byte, PropTable, LongBytes, LongStr, ShortStr, Short, Int, ULong};
 
 generate_protocol_methods! {
   Connection(10) {
     Start(10) { ver_major: Byte, ver_minor: Byte, properties: PropTable, mechanisms: LongStr, locales: LongStr, }
-    StartOk(11) { properties: PropTable, mechanism: ShortStr, response: LongStr, locale: ShortStr, }
+    StartOk(11) { properties: PropTable, mechanism: ShortStr, response: LongBytes, locale: ShortStr, }
+    Secure(20) { challenge: LongBytes, }
+    SecureOk(21) { response: LongBytes, }
     Tune(30) { chan_max: Short, frame_max: Int, heartbeat: Short, }
     TuneOk(31) { chan_max: Short, frame_max: Int, heartbeat: Short, }
     Open(40) { vhost: ShortStr, reserved1: ShortStr, reserved2: Byte, }
     OpenOk(41) { reserved1: ShortStr, }
     Close(50) { reply_code: Short, reply_text: ShortStr, class_id: Short, method_id: Short, }
     CloseOk(51) { }
+    Blocked(60) { reason: ShortStr, }
+    Unblocked(61) { }
+    UpdateSecret(70) { new_secret: LongStr, reason: ShortStr, }
+    UpdateSecretOk(71) { }
   }
   Channel(20) {
     Open(10) { reserved1: ShortStr, }
@@ -24,7 +31,7 @@ generate_protocol_methods! {
     Flow(20) { active: Byte, }
     FlowOk(21) { active: Byte, }
     Close(40) { reply_code: Short, reply_text: ShortStr, class_id: Short, method_id: Short, }
-    CloseOk(40) { }
+    CloseOk(41) { }
   }
   Exchange(40) {
     Declare(10) { reserved1: Short, name: ShortStr, ty: ShortStr, flags: Byte, props: PropTable, }
@@ -47,6 +54,17 @@ generate_protocol_methods! {
     Deliver(60) { consumer_tag: ShortStr, deliver_tag: Long, redelivered: Bool, exchange: ShortStr, routing_key: ShortStr, }
     Ack(80) { delivery_tag: Long, multiple: Bool, }
     Reject(90) { delivery_tag: Long, requeue: Bool, }
+    RecoverAsync(100) { requeue: Bool, }
+    Recover(110) { requeue: Bool, }
+    RecoverOk(111) { }
+  }
+  Tx(90) {
+    Select(10) { }
+    SelectOk(11) { }
+    Commit(20) { }
+    CommitOk(21) { }
+    Rollback(30) { }
+    RollbackOk(31) { }
   }
 }
 
@@ -55,45 +73,59 @@ pub struct ContentHeader {
   pub class_id: Short,
   pub body_len: Long,
   pub prop_list: MessageProperties,
+  // set when the properties sent by the peer couldn't be decoded
+  pub prop_error: Option<PropertiesError>,
 }
 
 impl ContentHeader {
-  pub fn from_raw_repr(mut buf: &[u8]) -> Self {
-    let class_id = buf.read_short().unwrap();
-    buf.read_short().unwrap();
-    let body_len = buf.read_long().unwrap();
-
-    Self {
-      class_id,
-      body_len,
-      prop_list: buf[12..].to_vec().into()
-    }
+  pub fn new(class_id: Short, body_len: Long, prop_list: MessageProperties) -> Self {
+    Self { class_id, body_len, prop_list, prop_error: None }
+  }
+
+  pub fn from_raw_repr(mut buf: &[u8]) -> Result<Self> {
+    let class_id = buf.read_short()?;
+    let _weight = buf.read_short()?;
+    let body_len = buf.read_long()?;
+
+    let (prop_list, prop_error) = match MessageProperties::try_from(buf) {
+      Ok(prop_list) => (prop_list, None),
+      Err(err) => (MessageProperties::default(), Some(err))
+    };
+
+    Ok(Self { class_id, body_len, prop_list, prop_error })
   }
 
   pub fn to_raw_repr(self) -> Vec<u8> {
     let mut buf = vec![];
-    buf.write_short(self.class_id).unwrap();
-    buf.write_short(0).unwrap();
-    buf.write_long(self.body_len as Long).unwrap();
-    buf.append(&mut self.prop_list.into());
+    self.write_raw_repr(&mut buf).unwrap();
     buf
   }
 
+  pub fn write_raw_repr<W: std::io::Write>(self, buf: &mut W) -> Result<()> {
+    buf.write_short(self.class_id)?;
+    buf.write_short(0)?;
+    buf.write_long(self.body_len as Long)?;
+    let prop_list: Vec<u8> = self.prop_list.try_into()?;
+    buf.write_all(&prop_list)?;
+    Ok(())
+  }
+
   pub fn into_frame(self) -> Frame {
-    Frame::ContentHeader(self)
+    Frame::ContentHeader(Box::new(self))
   }
 }
 
+// frames of 64KB and more share the memory of the read buffer they were split from, smaller ones are copied out of it
 #[derive(Debug)]
-pub struct ContentBody(pub Vec<u8>);
+pub struct ContentBody(pub Bytes);
 
 impl ContentBody {
-  pub fn from_raw_repr(mut buf: &[u8]) -> Self {
-    Self(buf.to_vec())
+  pub fn from_raw_repr(buf: Bytes) -> Self {
+    Self(buf)
   }
 
   pub fn to_raw_repr(self) -> Vec<u8> {
-    self.0
+    self.0.to_vec()
   }
 
   pub fn into_frame(self) -> Frame {
@@ -104,8 +136,9 @@ impl ContentBody {
 #[derive(Debug)]
 pub enum ContentFrame {
   WithMethod(Frame),
-  WithContentHeader((Frame, ContentHeader)),
-  WithBody((Frame, ContentHeader, ContentBody))
+  WithContentHeader((Frame, Box<ContentHeader>)),
+  // body frames are collected as they arrive and joined once the message is complete
+  WithBody((Frame, Box<ContentHeader>, Vec<Bytes>))
 }
 
 
@@ -114,7 +147,7 @@ impl ContentFrame {
     Self::WithMethod(method)
   }
 
-  pub fn with_content_header(self, header: ContentHeader) -> Self {
+  pub fn with_content_header(self, header: Box<ContentHeader>) -> Self {
     if let ContentFrame::WithMethod(frame) = self {
       Self::WithContentHeader((frame, header))
     } else {
@@ -122,14 +155,14 @@ impl ContentFrame {
     }
   }
 
-  pub fn with_body(self, mut body: ContentBody) -> Self {
+  pub fn with_body(self, body: ContentBody) -> Self {
     match self {
       ContentFrame::WithContentHeader((frame, header)) => {
-        Self::WithBody((frame, header, body))
+        Self::WithBody((frame, header, vec![body.0]))
       },
-      ContentFrame::WithBody((frame, header, mut curr_body)) => {
-        curr_body.0.append(&mut body.0);
-        Self::WithBody((frame, header, curr_body))
+      ContentFrame::WithBody((frame, header, mut chunks)) => {
+        chunks.push(body.0);
+        Self::WithBody((frame, header, chunks))
       },
       _ => {
         panic!("Invalid state transition")
@@ -139,14 +172,52 @@ impl ContentFrame {
 
   pub fn is_complete(&self) -> bool {
     match self {
-      ContentFrame::WithBody((_,header,body)) => {
-        header.body_len <= body.0.len() as Long
+      // empty messages have no body frames at all
+      ContentFrame::WithContentHeader((_, header)) => {
+        header.body_len == 0
+      },
+      ContentFrame::WithBody((_, header, chunks)) => {
+        header.body_len <= chunks.iter().map(|chunk| chunk.len()).sum::<usize>() as Long
       }
       _ => {
         false
       }
     }
   }
+
+  // a body that came in a single frame is handed out without copying
+  pub fn into_parts(self) -> Option<(Frame, Box<ContentHeader>, Bytes)> {
+    match self {
+      ContentFrame::WithContentHeader((frame, header)) => {
+        Some((frame, header, Bytes::new()))
+      },
+      ContentFrame::WithBody((frame, header, mut chunks)) => {
+        let body = if chunks.len() == 1 {
+          chunks.pop().unwrap()
+        } else {
+          let mut body = BytesMut::with_capacity(header.body_len as usize);
+          chunks.iter().for_each(|chunk| body.extend_from_slice(chunk));
+          body.freeze()
+        };
+        Some((frame, header, body))
+      },
+      ContentFrame::WithMethod(_) => None
+    }
+  }
 }
 
 pub type FrameEnvelope = (ChannelId, Frame);
+
+#[derive(Debug)]
+pub enum OutgoingFrame {
+  Single(FrameEnvelope),
+  // method, header and body of a message are queued as one item, so they can't be interleaved,
+  // the writer splits the body into frames without copying it
+  Content(ChannelId, Frame, Box<ContentHeader>, Bytes),
+}
+
+impl From<FrameEnvelope> for OutgoingFrame {
+  fn from(envelope: FrameEnvelope) -> Self {
+    Self::Single(envelope)
+  }
+}
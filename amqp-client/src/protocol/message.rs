@@ -1,14 +1,17 @@
 use std::cell::Cell;
-use std::io::Cursor;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::sync::Arc;
+use bytes::Bytes;
+use std::fmt::{Display, Formatter};
+use std::io::{Cursor, ErrorKind};
+use std::time::{Duration, SystemTime};
 use anyhow::bail;
-use tokio::sync::mpsc::UnboundedSender;
+use tracing::Span;
 use crate::protocol::dec::Decode;
 use crate::protocol::enc::Encode;
-use crate::protocol::frame::{BasicAck, BasicReject, FrameEnvelope};
-use crate::protocol::types::{ChannelId, PropTable};
+use crate::protocol::frame::{BasicAck, BasicReject};
+use crate::protocol::types::{self, ChannelId, PropTable, Property, ShortStr};
 use crate::Result;
+use crate::building_blocks::{DeliveryTracker, OutgoingTx};
 
 #[derive(Debug)]
 pub struct MessageMetadata {
@@ -16,6 +19,7 @@ pub struct MessageMetadata {
   redelivered: bool,
   exchange: String,
   routing_key: String,
+  consumer_tag: Arc<str>,
 }
 
 impl MessageMetadata {
@@ -23,13 +27,15 @@ impl MessageMetadata {
     delivery_tag: i64,
     redelivered: bool,
     exchange: String,
-    routing_key: String
+    routing_key: String,
+    consumer_tag: Arc<str>
   ) -> Self {
     Self {
       delivery_tag,
       redelivered,
       exchange,
-      routing_key
+      routing_key,
+      consumer_tag
     }
   }
 }
@@ -37,69 +43,149 @@ impl MessageMetadata {
 #[derive(Debug)]
 pub struct Message {
   channel: ChannelId,
-  outgoing_tx: UnboundedSender<FrameEnvelope>,
+  outgoing_tx: OutgoingTx,
+  tracker: Arc<DeliveryTracker>,
+  epoch: u64,
   properties: MessageProperties,
   metadata: MessageMetadata,
-  body: Vec<u8>,
-  is_processed: Cell<bool>
+  body: Bytes,
+  is_processed: Cell<bool>,
+  span: Span,
 }
 
 impl Message {
-  pub fn new(
+  pub(crate) fn new(
     channel: ChannelId,
-    outgoing_tx: UnboundedSender<FrameEnvelope>,
+    outgoing_tx: OutgoingTx,
+    tracker: Arc<DeliveryTracker>,
     properties: MessageProperties,
     metadata: MessageMetadata,
-    body: Vec<u8>
+    body: Bytes,
+    span: Span
   ) -> Self {
+    let epoch = tracker.track(metadata.delivery_tag, &metadata.consumer_tag);
+
     Self {
       channel,
       outgoing_tx,
+      tracker,
+      epoch,
       properties,
       metadata,
       body,
-      is_processed: Cell::new(false)
+      is_processed: Cell::new(false),
+      span
+    }
+  }
+
+  // carries the delivery tag and correlation id, e.g. for `handle(message).instrument(message.span().clone())`
+  pub fn span(&self) -> &Span {
+    &self.span
+  }
+
+  pub fn delivery_tag(&self) -> i64 {
+    self.metadata.delivery_tag
+  }
+
+  pub fn is_redelivered(&self) -> bool {
+    self.metadata.redelivered
+  }
+
+  pub fn exchange(&self) -> &str {
+    &self.metadata.exchange
+  }
+
+  pub fn routing_key(&self) -> &str {
+    &self.metadata.routing_key
+  }
+
+  pub fn consumer_tag(&self) -> &str {
+    &self.metadata.consumer_tag
+  }
+
+  // messages delivered before basic.recover can't be settled anymore
+  // returns how many deliveries the call settles, more than one for a multiple ack
+  fn settle(&self, multiple: bool) -> Result<usize> {
+    if self.is_processed.get() {
+      bail!("Already processed")
+    }
+
+    let settled = self.tracker.settle(self.epoch, self.metadata.delivery_tag, multiple);
+    if settled == 0 {
+      bail!("Delivery {} is no longer valid, it was settled or recovered", self.metadata.delivery_tag)
     }
+
+    self.is_processed.set(true);
+    Ok(settled)
   }
 
   pub fn get_body(&self) -> &[u8] {
-    self.body.as_slice()
+    &self.body
+  }
+
+  // a cheap reference counted handle, e.g. to keep the body after the message is acked
+  pub fn body(&self) -> Bytes {
+    self.body.clone()
   }
 
   pub fn get_properties(&self) -> &MessageProperties {
     &self.properties
   }
 
-  pub fn ack(&self, multiple: bool) -> Result<()> {
-    if self.is_processed.get() {
-      bail!("Already processed")
+  #[cfg(feature = "serde")]
+  pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+    use crate::protocol::serialization::{is_content_type, JSON_CONTENT_TYPE};
+
+    if !is_content_type(self.properties.content_type.as_ref(), JSON_CONTENT_TYPE) {
+      bail!("Expected {} content, got {:?}", JSON_CONTENT_TYPE, self.properties.content_type)
+    }
+
+    Ok(serde_json::from_slice(&self.body)?)
+  }
+
+  #[cfg(feature = "msgpack")]
+  pub fn msgpack<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+    use crate::protocol::serialization::{is_content_type, MSGPACK_CONTENT_TYPE};
+
+    if !is_content_type(self.properties.content_type.as_ref(), MSGPACK_CONTENT_TYPE) {
+      bail!("Expected {} content, got {:?}", MSGPACK_CONTENT_TYPE, self.properties.content_type)
     }
 
+    Ok(rmp_serde::from_slice(&self.body)?)
+  }
+
+  #[cfg(feature = "serde")]
+  pub fn headers_as<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+    let empty = PropTable::new();
+    crate::protocol::serialization::from_table(self.properties.headers.as_ref().unwrap_or(&empty))
+  }
+
+  pub fn ack(&self, multiple: bool) -> Result<()> {
+    let settled = self.settle(multiple)?;
+
     let method = BasicAck { delivery_tag: self.metadata.delivery_tag, multiple };
-    self.outgoing_tx.send((self.channel, method.into_frame()))?;
-    self.is_processed.set(true);
+    self.outgoing_tx.send((self.channel, method.into_frame()).into())?;
+    self.tracker.metrics().acked(settled);
     Ok(())
   }
 
   pub fn reject(&self, requeue: bool) -> Result<()> {
-    if self.is_processed.get() {
-      bail!("Already processed")
-    }
+    let settled = self.settle(false)?;
 
     let method = BasicReject { delivery_tag: self.metadata.delivery_tag, requeue };
-    self.outgoing_tx.send((self.channel, method.into_frame()))?;
-    self.is_processed.set(true);
+    self.outgoing_tx.send((self.channel, method.into_frame()).into())?;
+    self.tracker.metrics().rejected(settled);
     Ok(())
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageDeliveryMode {
   Persistent,
   NonPersistent
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct MessageProperties {
   pub content_type: Option<String>,
   pub content_encoding: Option<String>,
@@ -114,167 +200,497 @@ pub struct MessageProperties {
   pub ty: Option<String>,
   pub user_id: Option<String>,
   pub app_id: Option<String>,
-  reserved: String
+  pub cluster_id: Option<String>,
 }
 
 impl MessageProperties {
   pub fn new() -> Self {
     Default::default()
   }
+
+  pub fn builder() -> MessagePropertiesBuilder {
+    MessagePropertiesBuilder::new()
+  }
+
+  // encoding can only fail on oversized short strings, check them before the header is queued
+  pub fn validate(&self) -> std::result::Result<(), PropertiesError> {
+    let short_strings = [
+      ("content-type", &self.content_type),
+      ("content-encoding", &self.content_encoding),
+      ("correlation-id", &self.correlation_id),
+      ("reply-to", &self.reply_to),
+      ("expiration", &self.expiration),
+      ("message-id", &self.message_id),
+      ("type", &self.ty),
+      ("user-id", &self.user_id),
+      ("app-id", &self.app_id),
+      ("cluster-id", &self.cluster_id),
+    ];
+
+    for (field, value) in short_strings {
+      if let Some(value) = value {
+        check_shortstr(field, value)?;
+      }
+    }
+
+    if let Some(headers) = &self.headers {
+      check_table("headers", headers)?;
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct MessagePropertiesBuilder {
+  properties: MessageProperties
+}
+
+impl MessagePropertiesBuilder {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn build(self) -> MessageProperties {
+    self.properties
+  }
+
+  pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+    self.properties.content_type = Some(content_type.into());
+    self
+  }
+
+  pub fn content_encoding(mut self, content_encoding: impl Into<String>) -> Self {
+    self.properties.content_encoding = Some(content_encoding.into());
+    self
+  }
+
+  pub fn headers(mut self, headers: PropTable) -> Self {
+    self.properties.headers = Some(headers);
+    self
+  }
+
+  pub fn header(mut self, key: impl Into<ShortStr>, value: impl Into<Property>) -> Self {
+    self.properties.headers
+      .get_or_insert_with(PropTable::new)
+      .insert(key.into(), value.into());
+    self
+  }
+
+  pub fn delivery_mode(mut self, delivery_mode: MessageDeliveryMode) -> Self {
+    self.properties.delivery_mode = Some(delivery_mode);
+    self
+  }
+
+  pub fn persistent(self) -> Self {
+    self.delivery_mode(MessageDeliveryMode::Persistent)
+  }
+
+  pub fn transient(self) -> Self {
+    self.delivery_mode(MessageDeliveryMode::NonPersistent)
+  }
+
+  pub fn priority(mut self, priority: u8) -> Self {
+    self.properties.priority = Some(priority);
+    self
+  }
+
+  pub fn correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+    self.properties.correlation_id = Some(correlation_id.into());
+    self
+  }
+
+  pub fn reply_to(mut self, reply_to: impl Into<String>) -> Self {
+    self.properties.reply_to = Some(reply_to.into());
+    self
+  }
+
+  // the broker expects per-message TTL as a string of milliseconds
+  pub fn expiration(mut self, ttl: Duration) -> Self {
+    self.properties.expiration = Some(ttl.as_millis().to_string());
+    self
+  }
+
+  pub fn message_id(mut self, message_id: impl Into<String>) -> Self {
+    self.properties.message_id = Some(message_id.into());
+    self
+  }
+
+  // timestamps before the unix epoch can't be represented and are sent as the epoch itself
+  pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+    let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    self.properties.timestamp = Some(Duration::from_secs(since_epoch.as_secs()));
+    self
+  }
+
+  pub fn ty(mut self, ty: impl Into<String>) -> Self {
+    self.properties.ty = Some(ty.into());
+    self
+  }
+
+  pub fn user_id(mut self, user_id: impl Into<String>) -> Self {
+    self.properties.user_id = Some(user_id.into());
+    self
+  }
+
+  pub fn app_id(mut self, app_id: impl Into<String>) -> Self {
+    self.properties.app_id = Some(app_id.into());
+    self
+  }
+
+  pub fn cluster_id(mut self, cluster_id: impl Into<String>) -> Self {
+    self.properties.cluster_id = Some(cluster_id.into());
+    self
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertiesError {
+  Truncated(&'static str),
+  Malformed(&'static str, String),
+  TooLong(&'static str),
+}
+
+impl Display for PropertiesError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PropertiesError::Truncated(field) => {
+        write!(f, "Message properties truncated while reading {}", field)
+      },
+      PropertiesError::Malformed(field, reason) => {
+        write!(f, "Malformed {} message property: {}", field, reason)
+      },
+      PropertiesError::TooLong(field) => {
+        write!(f, "Message property {} exceeds 255 bytes", field)
+      }
+    }
+  }
+}
+
+impl std::error::Error for PropertiesError {}
+
+const CONTENT_TYPE_FLAG: u16 = 1 << 15;
+const CONTENT_ENCODING_FLAG: u16 = 1 << 14;
+const HEADERS_FLAG: u16 = 1 << 13;
+const DELIVERY_MODE_FLAG: u16 = 1 << 12;
+const PRIORITY_FLAG: u16 = 1 << 11;
+const CORRELATION_ID_FLAG: u16 = 1 << 10;
+const REPLY_TO_FLAG: u16 = 1 << 9;
+const EXPIRATION_FLAG: u16 = 1 << 8;
+const MESSAGE_ID_FLAG: u16 = 1 << 7;
+const TIMESTAMP_FLAG: u16 = 1 << 6;
+const TYPE_FLAG: u16 = 1 << 5;
+const USER_ID_FLAG: u16 = 1 << 4;
+const APP_ID_FLAG: u16 = 1 << 3;
+const CLUSTER_ID_FLAG: u16 = 1 << 2;
+const CONTINUATION_FLAG: u16 = 1;
+
+fn check_shortstr(field: &'static str, value: &str) -> std::result::Result<(), PropertiesError> {
+  if value.len() > u8::MAX as usize {
+    return Err(PropertiesError::TooLong(field));
+  }
+
+  Ok(())
+}
+
+fn check_table(field: &'static str, table: &PropTable) -> std::result::Result<(), PropertiesError> {
+  types::check_table(field, table).map_err(|_| PropertiesError::TooLong(field))
+}
+
+fn read_field<T>(field: &'static str, result: Result<T>) -> std::result::Result<T, PropertiesError> {
+  result.map_err(|err| {
+    match err.downcast_ref::<std::io::Error>() {
+      Some(io_err) if io_err.kind() == ErrorKind::UnexpectedEof => PropertiesError::Truncated(field),
+      _ => PropertiesError::Malformed(field, err.to_string())
+    }
+  })
+}
+
+fn write_field(field: &'static str, result: Result<()>) -> std::result::Result<(), PropertiesError> {
+  result.map_err(|err| PropertiesError::Malformed(field, err.to_string()))
+}
+
+fn write_shortstr_field(buf: &mut Vec<u8>, field: &'static str, value: String) -> std::result::Result<(), PropertiesError> {
+  check_shortstr(field, &value)?;
+  write_field(field, buf.write_shortstr(value.into()))
 }
 
-impl Into<Vec<u8>> for MessageProperties {
-  fn into(self) -> Vec<u8> {
-    let mut result = vec![];
-    let mut flag = 0_u16;
+impl TryFrom<MessageProperties> for Vec<u8> {
+  type Error = PropertiesError;
+
+  fn try_from(properties: MessageProperties) -> std::result::Result<Self, Self::Error> {
+    properties.validate()?;
+
+    let mut flags = 0_u16;
     let mut value = vec![];
 
-    if let Some(content_type) = self.content_type {
-      flag = flag | 0b1000_0000_0000_0000;
-      value.write_shortstr(content_type.into()).unwrap();
+    if let Some(content_type) = properties.content_type {
+      flags |= CONTENT_TYPE_FLAG;
+      write_shortstr_field(&mut value, "content-type", content_type)?;
     }
 
-    if let Some(content_encoding) = self.content_encoding {
-      flag = flag | 0b100_0000_0000_0000;
-      value.write_shortstr(content_encoding.into()).unwrap();
+    if let Some(content_encoding) = properties.content_encoding {
+      flags |= CONTENT_ENCODING_FLAG;
+      write_shortstr_field(&mut value, "content-encoding", content_encoding)?;
     }
 
-    if let Some(headers) = self.headers {
-      flag = flag | 0b10_0000_0000_0000;
-      value.write_proptable(headers).unwrap();
+    if let Some(headers) = properties.headers {
+      flags |= HEADERS_FLAG;
+      write_field("headers", value.write_proptable(headers))?;
     }
 
-    if let Some(delivery_mode) = self.delivery_mode {
-      flag = flag | 0b1_0000_0000_0000;
-      match delivery_mode {
-        MessageDeliveryMode::NonPersistent => {
-          value.write_byte(1).unwrap();
-        }
-        MessageDeliveryMode::Persistent => {
-          value.write_byte(2).unwrap();
-        }
-      }
+    if let Some(delivery_mode) = properties.delivery_mode {
+      flags |= DELIVERY_MODE_FLAG;
+      let mode = match delivery_mode {
+        MessageDeliveryMode::NonPersistent => 1,
+        MessageDeliveryMode::Persistent => 2
+      };
+      write_field("delivery-mode", value.write_byte(mode))?;
     }
 
-    if let Some(priority) = self.priority {
-      flag = flag | 0b1000_0000_0000;
-      value.write_byte(priority).unwrap();
+    if let Some(priority) = properties.priority {
+      flags |= PRIORITY_FLAG;
+      write_field("priority", value.write_byte(priority))?;
     }
 
-    if let Some(correlation_id) = self.correlation_id {
-      flag = flag | 0b100_0000_0000;
-      value.write_shortstr(correlation_id.into()).unwrap();
+    if let Some(correlation_id) = properties.correlation_id {
+      flags |= CORRELATION_ID_FLAG;
+      write_shortstr_field(&mut value, "correlation-id", correlation_id)?;
     }
 
-    if let Some(reply_to) = self.reply_to {
-      flag = flag | 0b10_0000_0000;
-      value.write_shortstr(reply_to.into()).unwrap();
+    if let Some(reply_to) = properties.reply_to {
+      flags |= REPLY_TO_FLAG;
+      write_shortstr_field(&mut value, "reply-to", reply_to)?;
     }
 
-    if let Some(expiration) = self.expiration {
-      flag = flag | 0b1_0000_0000;
-      value.write_shortstr(expiration.into()).unwrap();
+    if let Some(expiration) = properties.expiration {
+      flags |= EXPIRATION_FLAG;
+      write_shortstr_field(&mut value, "expiration", expiration)?;
     }
 
-    if let Some(message_id) = self.message_id {
-      flag = flag | 0b1000_0000;
-      value.write_shortstr(message_id.into()).unwrap();
+    if let Some(message_id) = properties.message_id {
+      flags |= MESSAGE_ID_FLAG;
+      write_shortstr_field(&mut value, "message-id", message_id)?;
     }
 
-    if let Some(timestamp) = self.timestamp {
-      flag = flag | 0b100_0000;
-      value.write_ulong(timestamp.as_secs()).unwrap();
+    if let Some(timestamp) = properties.timestamp {
+      flags |= TIMESTAMP_FLAG;
+      write_field("timestamp", value.write_ulong(timestamp.as_secs()))?;
     }
 
-    if let Some(ty) = self.ty {
-      flag = flag | 0b10_0000;
-      value.write_shortstr(ty.into()).unwrap();
+    if let Some(ty) = properties.ty {
+      flags |= TYPE_FLAG;
+      write_shortstr_field(&mut value, "type", ty)?;
     }
 
+    if let Some(user_id) = properties.user_id {
+      flags |= USER_ID_FLAG;
+      write_shortstr_field(&mut value, "user-id", user_id)?;
+    }
 
-    if let Some(user_id) = self.user_id {
-      flag = flag | 0b1_0000;
-      value.write_shortstr(user_id.into()).unwrap();
+    if let Some(app_id) = properties.app_id {
+      flags |= APP_ID_FLAG;
+      write_shortstr_field(&mut value, "app-id", app_id)?;
     }
 
-    if let Some(app_id) = self.app_id {
-      flag = flag | 0b1000;
-      value.write_shortstr(app_id.into()).unwrap();
+    if let Some(cluster_id) = properties.cluster_id {
+      flags |= CLUSTER_ID_FLAG;
+      write_shortstr_field(&mut value, "cluster-id", cluster_id)?;
     }
 
-    result.write_ushort(flag).unwrap();
+    let mut result = Vec::with_capacity(value.len() + 2);
+    write_field("property-flags", result.write_ushort(flags))?;
     result.append(&mut value);
 
-    result
+    Ok(result)
   }
 }
 
-impl From<Vec<u8>> for MessageProperties {
-  fn from(mut data: Vec<u8>) -> Self {
+impl TryFrom<&[u8]> for MessageProperties {
+  type Error = PropertiesError;
+
+  fn try_from(data: &[u8]) -> std::result::Result<Self, Self::Error> {
     let mut cursor = Cursor::new(data);
-    let flag = cursor.read_ushort().unwrap();
+    let flags = read_field("property-flags", cursor.read_ushort())?;
+
+    // basic class defines no properties past the first flags word, skip any continuation
+    let mut continuation = flags;
+    while continuation & CONTINUATION_FLAG != 0 {
+      continuation = read_field("property-flags", cursor.read_ushort())?;
+    }
+
     let mut fields = MessageProperties::new();
 
-    if (flag & 0b1000_0000_0000_0000 ) != 0 {
-      fields.content_type = Some(cursor.read_shortstr().unwrap().0);
+    if flags & CONTENT_TYPE_FLAG != 0 {
+      fields.content_type = Some(read_field("content-type", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b100_0000_0000_0000 ) != 0 {
-      fields.content_encoding = Some(cursor.read_shortstr().unwrap().0);
+    if flags & CONTENT_ENCODING_FLAG != 0 {
+      fields.content_encoding = Some(read_field("content-encoding", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b10_0000_0000_0000 ) != 0 {
-      fields.headers = Some(cursor.read_proptable().unwrap());
+    if flags & HEADERS_FLAG != 0 {
+      fields.headers = Some(read_field("headers", cursor.read_proptable())?);
     }
 
-    if (flag & 0b1_0000_0000_0000 ) != 0 {
-      let mode = cursor.read_byte().unwrap();
+    if flags & DELIVERY_MODE_FLAG != 0 {
+      fields.delivery_mode = match read_field("delivery-mode", cursor.read_byte())? {
+        1 => Some(MessageDeliveryMode::NonPersistent),
+        2 => Some(MessageDeliveryMode::Persistent),
+        mode => {
+          return Err(PropertiesError::Malformed("delivery-mode", format!("unknown mode {}", mode)));
+        }
+      };
+    }
 
-      fields.delivery_mode = Some(if mode == 2 {
-        MessageDeliveryMode::Persistent
-      } else {
-        MessageDeliveryMode::NonPersistent
-      });
+    if flags & PRIORITY_FLAG != 0 {
+      fields.priority = Some(read_field("priority", cursor.read_byte())?);
+    }
 
-      fields.headers = Some(cursor.read_proptable().unwrap());
+    if flags & CORRELATION_ID_FLAG != 0 {
+      fields.correlation_id = Some(read_field("correlation-id", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b1000_0000_0000 ) != 0 {
-      fields.priority = Some(cursor.read_byte().unwrap());
+    if flags & REPLY_TO_FLAG != 0 {
+      fields.reply_to = Some(read_field("reply-to", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b100_0000_0000 ) != 0 {
-      fields.correlation_id = Some(cursor.read_shortstr().unwrap().0);
+    if flags & EXPIRATION_FLAG != 0 {
+      fields.expiration = Some(read_field("expiration", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b10_0000_0000 ) != 0 {
-      fields.reply_to = Some(cursor.read_shortstr().unwrap().0);
+    if flags & MESSAGE_ID_FLAG != 0 {
+      fields.message_id = Some(read_field("message-id", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b1_0000_0000 ) != 0 {
-      fields.expiration = Some(cursor.read_shortstr().unwrap().0);
+    if flags & TIMESTAMP_FLAG != 0 {
+      fields.timestamp = Some(Duration::from_secs(read_field("timestamp", cursor.read_ulong())?));
     }
 
-    if (flag & 0b1000_0000 ) != 0 {
-      fields.message_id = Some(cursor.read_shortstr().unwrap().0);
+    if flags & TYPE_FLAG != 0 {
+      fields.ty = Some(read_field("type", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b100_0000 ) != 0 {
-      fields.timestamp = Some(Duration::from_secs(cursor.read_ulong().unwrap()));
+    if flags & USER_ID_FLAG != 0 {
+      fields.user_id = Some(read_field("user-id", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b10_0000 ) != 0 {
-      fields.ty = Some(cursor.read_shortstr().unwrap().0);
+    if flags & APP_ID_FLAG != 0 {
+      fields.app_id = Some(read_field("app-id", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b1_0000 ) != 0 {
-      fields.user_id = Some(cursor.read_shortstr().unwrap().0);
+    if flags & CLUSTER_ID_FLAG != 0 {
+      fields.cluster_id = Some(read_field("cluster-id", cursor.read_shortstr())?.0);
     }
 
-    if (flag & 0b1000 ) != 0 {
-      fields.app_id = Some(cursor.read_shortstr().unwrap().0);
+    Ok(fields)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn all_properties() -> MessageProperties {
+    MessageProperties::builder()
+      .content_type("application/json")
+      .content_encoding("gzip")
+      .headers(PropTable::from([("trace".into(), Property::LongStr("abc".into()))]))
+      .delivery_mode(MessageDeliveryMode::Persistent)
+      .priority(5)
+      .correlation_id("correlation")
+      .reply_to("replies")
+      .expiration(Duration::from_secs(60))
+      .message_id("message")
+      .timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+      .ty("order.created")
+      .user_id("guest")
+      .app_id("billing")
+      .cluster_id("cluster")
+      .build()
+  }
+
+  fn encode(properties: MessageProperties) -> Vec<u8> {
+    properties.try_into().unwrap()
+  }
+
+  #[test]
+  fn round_trips_all_properties() {
+    let decoded = MessageProperties::try_from(&encode(all_properties())[..]).unwrap();
+
+    assert_eq!(decoded.content_type.as_deref(), Some("application/json"));
+    assert_eq!(decoded.content_encoding.as_deref(), Some("gzip"));
+    match decoded.headers.unwrap().get(&"trace".into()) {
+      Some(Property::LongStr(value)) => assert_eq!(value.0, "abc"),
+      other => panic!("unexpected header {:?}", other)
     }
+    assert_eq!(decoded.delivery_mode, Some(MessageDeliveryMode::Persistent));
+    assert_eq!(decoded.priority, Some(5));
+    assert_eq!(decoded.correlation_id.as_deref(), Some("correlation"));
+    assert_eq!(decoded.reply_to.as_deref(), Some("replies"));
+    assert_eq!(decoded.expiration.as_deref(), Some("60000"));
+    assert_eq!(decoded.message_id.as_deref(), Some("message"));
+    assert_eq!(decoded.timestamp, Some(Duration::from_secs(1_700_000_000)));
+    assert_eq!(decoded.ty.as_deref(), Some("order.created"));
+    assert_eq!(decoded.user_id.as_deref(), Some("guest"));
+    assert_eq!(decoded.app_id.as_deref(), Some("billing"));
+    assert_eq!(decoded.cluster_id.as_deref(), Some("cluster"));
+  }
+
+  #[test]
+  fn encodes_cluster_id_last() {
+    let encoded = encode(MessageProperties::builder().cluster_id("cluster").build());
+
+    assert_eq!(encoded, [&CLUSTER_ID_FLAG.to_be_bytes()[..], &[7], b"cluster"].concat());
+  }
+
+  #[test]
+  fn skips_continuation_flags() {
+    let flags = CONTENT_TYPE_FLAG | CONTINUATION_FLAG;
+    let data = [&flags.to_be_bytes()[..], &CONTINUATION_FLAG.to_be_bytes(), &[0, 0], &[4], b"text"].concat();
+
+    let decoded = MessageProperties::try_from(&data[..]).unwrap();
+
+    assert_eq!(decoded.content_type.as_deref(), Some("text"));
+  }
+
+  #[test]
+  fn reports_truncated_properties() {
+    let encoded = encode(all_properties());
+
+    for len in [1, encoded.len() - 1] {
+      assert!(
+        matches!(MessageProperties::try_from(&encoded[..len]), Err(PropertiesError::Truncated(_))),
+        "{} of {} bytes", len, encoded.len()
+      );
+    }
+
+    let encoded = encode(MessageProperties::builder().message_id("message").build());
+    assert_eq!(MessageProperties::try_from(&encoded[..5]).unwrap_err(), PropertiesError::Truncated("message-id"));
+  }
+
+  #[test]
+  fn rejects_unknown_delivery_mode() {
+    let data = [&DELIVERY_MODE_FLAG.to_be_bytes()[..], &[3]].concat();
+
+    assert!(matches!(
+      MessageProperties::try_from(&data[..]),
+      Err(PropertiesError::Malformed("delivery-mode", _))
+    ));
+  }
+
+  #[test]
+  fn rejects_oversized_short_strings() {
+    let long = "x".repeat(256);
+
+    let properties = MessageProperties::builder().message_id(long.clone()).build();
+    assert_eq!(properties.validate(), Err(PropertiesError::TooLong("message-id")));
 
-    fields
+    let nested = PropTable::from([("inner".into(), Property::ShortStr(long.as_str().into()))]);
+    let properties = MessageProperties::builder()
+      .headers(PropTable::from([("outer".into(), Property::Table(nested))]))
+      .build();
+    assert_eq!(properties.validate(), Err(PropertiesError::TooLong("headers")));
+    assert!(Vec::<u8>::try_from(properties).is_err());
   }
 }
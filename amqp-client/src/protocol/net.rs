@@ -1,4 +1,10 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
 mod reader;
 mod writer;
-pub(crate) use reader::FrameReader;
-pub(crate) use writer::FrameWriter;
+pub use reader::FrameReader;
+pub use writer::FrameWriter;
+
+// the connection works with any transport, e.g. TCP or an in-memory duplex
+pub(crate) type TransportReader = FrameReader<Box<dyn AsyncRead + Send + Unpin>>;
+pub(crate) type TransportWriter = FrameWriter<Box<dyn AsyncWrite + Send + Unpin>>;
@@ -1,30 +1,39 @@
 use std::io::Cursor;
-use std::sync::{Mutex};
 use anyhow::bail;
-use bytes::{Buf, BytesMut};
-use tokio::io::{AsyncReadExt, BufReader};
-use tokio::net::tcp::OwnedReadHalf;
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use crate::protocol::dec::Decode;
 use crate::{Result};
 use crate::protocol::types::{ChannelId};
 use crate::protocol::frame::{ContentBody, ContentHeader, Frame};
+use crate::building_blocks::{ConnectionMetrics, FrameMetrics};
 
 const FRAME_HEADER_SIZE: usize = 7;
 const FRAME_END_SIZE: usize = 1;
+// spare room kept in the read buffer, frames handed out may still hold on to its older parts
+const READ_RESERVE: usize = 64 * 1024;
+// smaller bodies are copied out, a message kept unacked must not pin a whole read buffer
+const ZERO_COPY_THRESHOLD: usize = READ_RESERVE;
 
-pub struct FrameReader {
-  inner: BufReader<OwnedReadHalf>,
+pub struct FrameReader<R> {
+  inner: R,
   buf: BytesMut,
+  metrics: Option<FrameMetrics>,
 }
 
-impl FrameReader {
-  pub fn new(inner: BufReader<OwnedReadHalf>) -> Self {
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+  pub fn new(inner: R) -> Self {
     Self {
       inner,
       buf: BytesMut::with_capacity(128 * 1024),
+      metrics: None,
     }
   }
 
+  pub(crate) fn set_metrics(&mut self, metrics: ConnectionMetrics) {
+    self.metrics = Some(metrics.frames_received());
+  }
+
   pub async fn next_frame(&mut self) -> Result<(ChannelId, Frame)> {
     loop {
       if let Some(amqp_frame) = self.parse_frame()? {
@@ -35,7 +44,7 @@ impl FrameReader {
         continue
       }
 
-      // todo: what if no capacity left?
+      self.buf.reserve(READ_RESERVE);
       if 0 == self.inner.read_buf(&mut self.buf).await? {
         // todo: add check for size of the buf, if its error or connection close
         bail!("Failed to read. Connection closed")
@@ -54,34 +63,31 @@ impl FrameReader {
     let chan = header.read_short()?;
     let size = header.read_int()?;
 
-    let body = self.buf.split_to(size as usize).to_vec();
+    // frozen rather than copied, so content bodies can share the read buffer
+    let body = self.buf.split_to(size as usize).freeze();
     // read frame end byte
     assert_eq!(206, self.buf[0]);
     self.buf.advance(1);
+    if let Some(metrics) = &mut self.metrics {
+      metrics.frame(chan, FRAME_HEADER_SIZE + size as usize + FRAME_END_SIZE);
+    }
 
     let frame = match frame_type {
       1 => {
-        let mut meta = Cursor::new(body[..4].to_vec());
+        let mut meta = Cursor::new(&body[..4]);
         let class_id = meta.read_short()?;
         let method_id = meta.read_short()?;
 
         Frame::method(class_id, method_id, &body)
       },
       2 => {
-        let mut meta = Cursor::new(body[..12].to_vec());
-        let class_id = meta.read_short()?;
-        let _weight = meta.read_short()?;
-        // todo: review type
-        let body_len = meta.read_long()?;
-
-        Frame::ContentHeader(ContentHeader {
-          class_id,
-          body_len,
-          prop_list: body[12..].to_vec().into()
-        })
+        ContentHeader::from_raw_repr(&body)?.into_frame()
+      }
+      3 if body.len() < ZERO_COPY_THRESHOLD => {
+        Frame::ContentBody(ContentBody::from_raw_repr(Bytes::copy_from_slice(&body)))
       }
       3 => {
-        Frame::ContentBody(ContentBody(body))
+        Frame::ContentBody(ContentBody::from_raw_repr(body))
       }
       8 => {
         Frame::Heartbeat
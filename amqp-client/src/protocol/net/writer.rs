@@ -1,20 +1,126 @@
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::net::tcp::{OwnedWriteHalf};
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use anyhow::bail;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::debug;
 use crate::protocol::types::{ChannelId};
-use crate::protocol::frame::{Frame};
+use crate::protocol::frame::{ContentHeader, Frame, OutgoingFrame};
+use crate::building_blocks::{ConnectionMetrics, FrameMetrics};
 use crate::{Result};
-use crate::protocol::enc::Encode;
 
-pub struct FrameWriter {
-  inner: BufWriter<OwnedWriteHalf>
+// frame type, channel, size and frame end byte
+const FRAME_OVERHEAD: usize = 8;
+// the smallest frame_max a peer is allowed to negotiate
+const MIN_FRAME_SIZE: usize = 4096;
+const FRAME_END: u8 = 0xCE;
+// smaller body chunks are cheaper to copy into the buffer than to pass as a separate slice
+const ZERO_COPY_THRESHOLD: usize = 1024;
+// most platforms cap the number of slices a single vectored write takes
+const MAX_IO_SLICES: usize = 64;
+
+// frames are encoded into a reusable buffer and written out together on `flush`,
+// body chunks above the threshold are referenced instead of copied
+pub struct FrameWriter<W> {
+  inner: W,
+  buf: BytesMut,
+  segments: VecDeque<Bytes>,
+  buffered: usize,
+  frame_max: usize,
+  metrics: Option<FrameMetrics>,
 }
 
-impl FrameWriter {
-  pub fn new(inner: BufWriter<OwnedWriteHalf>) -> Self {
-    Self { inner }
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+  pub fn new(inner: W) -> Self {
+    Self {
+      inner,
+      buf: BytesMut::with_capacity(MIN_FRAME_SIZE),
+      segments: VecDeque::new(),
+      buffered: 0,
+      frame_max: MIN_FRAME_SIZE,
+      metrics: None
+    }
+  }
+
+  // frames are counted as they are encoded, a failed flush closes the connection anyway
+  pub(crate) fn set_metrics(&mut self, metrics: ConnectionMetrics) {
+    self.metrics = Some(metrics.frames_sent());
+  }
+
+  // zero stands for no limit on the frame size
+  pub fn set_frame_max(&mut self, frame_max: usize) {
+    self.frame_max = frame_max;
+  }
+
+  // bytes queued by `enqueue` and not flushed yet
+  pub fn buffered(&self) -> usize {
+    self.buffered
   }
 
   pub async fn dispatch(&mut self, channel: ChannelId, frame: Frame) -> Result<()> {
+    self.encode_frame(channel, frame)?;
+    self.flush().await
+  }
+
+  pub fn enqueue(&mut self, outgoing: OutgoingFrame) -> Result<()> {
+    match outgoing {
+      OutgoingFrame::Single((channel, frame)) => {
+        self.encode_frame(channel, frame)
+      },
+      OutgoingFrame::Content(channel, method, header, body) => {
+        // a method whose header failed to encode must not go out alone
+        let (start, buffered) = (self.buf.len(), self.buffered);
+        let encoded = self.encode_content(channel, method, header, body);
+        if encoded.is_err() {
+          self.buf.truncate(start);
+          self.buffered = buffered;
+        }
+        encoded
+      }
+    }
+  }
+
+  fn encode_content(&mut self, channel: ChannelId, method: Frame, header: Box<ContentHeader>, body: Bytes) -> Result<()> {
+    self.encode_frame(channel, method)?;
+    self.encode_frame(channel, Frame::ContentHeader(header))?;
+
+    let chunk_size = match self.frame_max {
+      0 => body.len().max(1),
+      frame_max => frame_max - FRAME_OVERHEAD
+    };
+
+    let mut offset = 0;
+    while offset < body.len() {
+      let end = body.len().min(offset + chunk_size);
+      self.encode_body_chunk(channel, body.slice(offset..end));
+      offset = end;
+    }
+
+    Ok(())
+  }
+
+  fn encode_body_chunk(&mut self, channel: ChannelId, chunk: Bytes) {
+    let chunk_len = chunk.len();
+    self.buf.put_u8(3);
+    self.buf.put_i16(channel);
+    self.buf.put_u32(chunk_len as u32);
+
+    if chunk_len < ZERO_COPY_THRESHOLD {
+      self.buf.put_slice(&chunk);
+    } else {
+      self.segments.push_back(self.buf.split().freeze());
+      self.segments.push_back(chunk);
+    }
+
+    self.buf.put_u8(FRAME_END);
+    self.buffered += chunk_len + FRAME_OVERHEAD;
+    if let Some(metrics) = &mut self.metrics {
+      metrics.frame(channel, chunk_len + FRAME_OVERHEAD);
+    }
+  }
+
+  fn encode_frame(&mut self, channel: ChannelId, frame: Frame) -> Result<()> {
+    debug!(channel, method = frame.name(), "frame sent");
     let frame_ty = match &frame {
       Frame::ContentHeader(..) => 2,
       Frame::ContentBody(..) => 3,
@@ -22,23 +128,158 @@ impl FrameWriter {
       _ => 1,
     };
 
-    let mut payload = frame.to_raw_repr();
-    let mut frame_buff = vec![];
+    let start = self.buf.len();
+    self.buf.put_u8(frame_ty);
+    self.buf.put_i16(channel);
+    // the size is patched once the payload is encoded
+    self.buf.put_u32(0);
 
-    frame_buff.write_byte(frame_ty).unwrap();
-    frame_buff.write_short(channel).unwrap();
-    frame_buff.write_uint(payload.len() as u32).unwrap();
-    frame_buff.append(&mut payload);
-    frame_buff.write_byte(0xCE).unwrap();
+    let payload_start = self.buf.len();
+    // nothing of a frame that failed to encode may reach the socket
+    if let Err(err) = frame.write_raw_repr(&mut (&mut self.buf).writer()) {
+      self.buf.truncate(start);
+      return Err(err);
+    }
+    let payload_len = (self.buf.len() - payload_start) as u32;
+    self.buf[payload_start - 4..payload_start].copy_from_slice(&payload_len.to_be_bytes());
+    self.buf.put_u8(FRAME_END);
 
-    self.write_binary(&frame_buff).await?;
+    self.buffered += self.buf.len() - start;
+    if let Some(metrics) = &mut self.metrics {
+      metrics.frame(channel, self.buf.len() - start);
+    }
+    Ok(())
+  }
+
+  // writes everything enqueued so far with as few syscalls as possible
+  pub async fn flush(&mut self) -> Result<()> {
+    if !self.buf.is_empty() {
+      self.segments.push_back(self.buf.split().freeze());
+    }
+
+    while !self.segments.is_empty() {
+      let slices: Vec<IoSlice> = self.segments.iter()
+        .take(MAX_IO_SLICES)
+        .map(|segment| IoSlice::new(segment))
+        .collect();
+      let written = self.inner.write_vectored(&slices).await?;
+
+      if written == 0 {
+        bail!("Failed to write. Connection closed")
+      }
+
+      self.advance(written);
+    }
+
+    self.buffered = 0;
+    self.inner.flush().await?;
 
     Ok(())
   }
 
+  fn advance(&mut self, mut written: usize) {
+    while written > 0 {
+      let segment = self.segments.front_mut().unwrap();
+
+      if segment.len() <= written {
+        written -= segment.len();
+        self.segments.pop_front();
+      } else {
+        segment.advance(written);
+        written = 0;
+      }
+    }
+  }
+
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+
   pub async fn write_binary<'a>(&'a mut self, buf: &'a [u8]) -> Result<()> {
     self.inner.write_all(buf).await?;
     self.inner.flush().await?;
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::frame::BasicPublish;
+  use crate::protocol::message::MessageProperties;
+
+  const HEARTBEAT: [u8; 8] = [8, 0, 0, 0, 0, 0, 0, FRAME_END];
+
+  fn content(channel: ChannelId, properties: MessageProperties, body: Bytes) -> OutgoingFrame {
+    let method = BasicPublish { reserved1: 0, exchange: "".into(), routing_key: "key".into(), flags: 0 };
+    let header = ContentHeader::new(60, body.len() as _, properties);
+    OutgoingFrame::Content(channel, method.into_frame(), Box::new(header), body)
+  }
+
+  // type, channel and payload size of every frame written
+  async fn written(frame_max: usize, outgoing: Vec<OutgoingFrame>) -> Vec<(u8, ChannelId, usize)> {
+    let mut writer = FrameWriter::new(vec![]);
+    writer.set_frame_max(frame_max);
+    for outgoing in outgoing {
+      writer.enqueue(outgoing).unwrap();
+    }
+    writer.flush().await.unwrap();
+
+    let mut buf = Bytes::from(writer.into_inner());
+    let mut frames = vec![];
+    while buf.has_remaining() {
+      let (ty, channel, size) = (buf.get_u8(), buf.get_i16(), buf.get_u32() as usize);
+      assert!(size + FRAME_OVERHEAD <= frame_max);
+      buf.advance(size);
+      assert_eq!(buf.get_u8(), FRAME_END);
+      frames.push((ty, channel, size));
+    }
+    frames
+  }
+
+  fn body_sizes(frames: &[(u8, ChannelId, usize)]) -> Vec<usize> {
+    frames.iter().filter(|(ty, ..)| *ty == 3).map(|(.., size)| *size).collect()
+  }
+
+  #[tokio::test]
+  async fn splits_bodies_at_the_frame_max() {
+    let chunk = MIN_FRAME_SIZE - FRAME_OVERHEAD;
+
+    for (body_len, sizes) in [(chunk, vec![chunk]), (chunk + 1, vec![chunk, 1]), (chunk * 2, vec![chunk, chunk])] {
+      let frames = written(MIN_FRAME_SIZE, vec![content(1, Default::default(), vec![7; body_len].into())]).await;
+      assert_eq!(body_sizes(&frames), sizes);
+    }
+  }
+
+  #[tokio::test]
+  async fn writes_no_body_frame_for_an_empty_body() {
+    let frames = written(MIN_FRAME_SIZE, vec![content(1, Default::default(), Bytes::new())]).await;
+    assert_eq!(frames.iter().map(|(ty, ..)| *ty).collect::<Vec<_>>(), [1, 2]);
+  }
+
+  #[tokio::test]
+  async fn keeps_the_frames_of_a_message_together() {
+    let body = Bytes::from(vec![7; MIN_FRAME_SIZE * 2]);
+    let frames = written(MIN_FRAME_SIZE, vec![
+      content(1, Default::default(), body.clone()),
+      (0, Frame::Heartbeat).into(),
+      content(2, Default::default(), body),
+    ]).await;
+
+    let layout: Vec<(u8, ChannelId)> = frames.iter().map(|(ty, channel, _)| (*ty, *channel)).collect();
+    assert_eq!(layout, [(1, 1), (2, 1), (3, 1), (3, 1), (3, 1), (8, 0), (1, 2), (2, 2), (3, 2), (3, 2), (3, 2)]);
+  }
+
+  #[tokio::test]
+  async fn drops_content_whose_header_fails_to_encode() {
+    let mut writer = FrameWriter::new(vec![]);
+    let properties = MessageProperties { content_type: Some("x".repeat(300)), ..Default::default() };
+
+    assert!(writer.enqueue(content(1, properties, Bytes::from_static(b"body"))).is_err());
+    assert_eq!(writer.buffered(), 0);
+
+    writer.enqueue((0, Frame::Heartbeat).into()).unwrap();
+    writer.flush().await.unwrap();
+    assert_eq!(writer.into_inner(), HEARTBEAT);
+  }
+}
@@ -0,0 +1,55 @@
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use tracing::{warn, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::protocol::message::MessageProperties;
+use crate::protocol::types::{PropTable, Property};
+
+// trace context travels in the message headers, e.g. W3C `traceparent`/`tracestate`,
+// with whatever propagator the application installed via `global::set_text_map_propagator`
+// the headers table is only created once the propagator actually writes something
+struct HeaderInjector<'a>(&'a mut Option<PropTable>);
+
+impl Injector for HeaderInjector<'_> {
+  fn set(&mut self, key: &str, value: String) {
+    self.0.get_or_insert_with(PropTable::new).insert(key.into(), Property::LongStr(value.into()));
+  }
+}
+
+struct HeaderExtractor<'a>(&'a PropTable);
+
+impl Extractor for HeaderExtractor<'_> {
+  fn get(&self, key: &str) -> Option<&str> {
+    match self.0.get(&key.into())? {
+      Property::LongStr(value) => Some(&value.0),
+      Property::ShortStr(value) => Some(&value.0),
+      _ => None
+    }
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(|key| key.0.as_str()).collect()
+  }
+}
+
+// the publish span's context goes out with the message
+pub(crate) fn inject(span: &Span, properties: &mut MessageProperties) {
+  let context = span.context();
+
+  global::get_text_map_propagator(|propagator| {
+    propagator.inject_context(&context, &mut HeaderInjector(&mut properties.headers))
+  });
+}
+
+// makes the publisher's span the parent of the delivery span, if the message carries one
+pub(crate) fn extract(span: &Span, properties: &MessageProperties) {
+  let headers = match &properties.headers {
+    Some(headers) => headers,
+    None => return
+  };
+
+  let context = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+  if let Err(err) = span.set_parent(context) {
+    warn!("failed to continue the trace of a delivery: {}", err);
+  }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use serde::de::{DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::protocol::types::{LongStr, Property, PropTable, ShortStr};
+use crate::Result;
+
+pub static JSON_CONTENT_TYPE: &str = "application/json";
+#[cfg(feature = "msgpack")]
+pub static MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+impl Serialize for ShortStr {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&self.0)
+  }
+}
+
+impl<'de> Deserialize<'de> for ShortStr {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    Ok(ShortStr(String::deserialize(deserializer)?))
+  }
+}
+
+impl Serialize for Property {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match self {
+      Property::Bool(v) => serializer.serialize_bool(*v),
+      Property::Byte(v) => serializer.serialize_u8(*v),
+      Property::Short(v) => serializer.serialize_i16(*v),
+      Property::UShort(v) => serializer.serialize_u16(*v),
+      Property::Int(v) => serializer.serialize_i32(*v),
+      Property::UInt(v) => serializer.serialize_u32(*v),
+      Property::Long(v) => serializer.serialize_i64(*v),
+      Property::ULong(v) => serializer.serialize_u64(*v),
+      Property::Float(v) => serializer.serialize_f32(*v),
+      Property::Double(v) => serializer.serialize_f64(*v),
+      Property::ShortStr(v) => serializer.serialize_str(&v.0),
+      Property::LongStr(v) => serializer.serialize_str(&v.0),
+      Property::Decimal(scale, v) => serializer.serialize_f64(*v as f64 / 10_f64.powi(*scale as i32)),
+      Property::Timestamp(v) => serializer.serialize_u64(*v),
+      Property::Array(v) => serializer.collect_seq(v),
+      Property::ByteArray(v) => serializer.serialize_bytes(v),
+      Property::Void => serializer.serialize_unit(),
+      Property::Table(v) => serializer.collect_map(v),
+    }
+  }
+}
+
+struct PropertyVisitor;
+
+impl<'de> Visitor<'de> for PropertyVisitor {
+  type Value = Property;
+
+  fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+    formatter.write_str("a value representable in an AMQP field table")
+  }
+
+  fn visit_bool<E>(self, v: bool) -> std::result::Result<Property, E> {
+    Ok(Property::Bool(v))
+  }
+
+  fn visit_i64<E>(self, v: i64) -> std::result::Result<Property, E> {
+    Ok(Property::Long(v))
+  }
+
+  fn visit_u64<E>(self, v: u64) -> std::result::Result<Property, E> {
+    Ok(i64::try_from(v).map(Property::Long).unwrap_or(Property::ULong(v)))
+  }
+
+  fn visit_f64<E>(self, v: f64) -> std::result::Result<Property, E> {
+    Ok(Property::Double(v))
+  }
+
+  fn visit_str<E>(self, v: &str) -> std::result::Result<Property, E> {
+    Ok(Property::LongStr(LongStr(v.into())))
+  }
+
+  fn visit_string<E>(self, v: String) -> std::result::Result<Property, E> {
+    Ok(Property::LongStr(LongStr(v)))
+  }
+
+  fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Property, E> {
+    Ok(Property::ByteArray(v.to_vec()))
+  }
+
+  fn visit_none<E>(self) -> std::result::Result<Property, E> {
+    Ok(Property::Void)
+  }
+
+  fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Property, D::Error> {
+    Property::deserialize(deserializer)
+  }
+
+  fn visit_unit<E>(self) -> std::result::Result<Property, E> {
+    Ok(Property::Void)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Property, A::Error> {
+    let mut array = vec![];
+
+    while let Some(item) = seq.next_element()? {
+      array.push(item);
+    }
+
+    Ok(Property::Array(array))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Property, A::Error> {
+    let mut table = HashMap::new();
+
+    while let Some((key, value)) = map.next_entry::<ShortStr, Property>()? {
+      table.insert(key, value);
+    }
+
+    Ok(Property::Table(table))
+  }
+}
+
+impl<'de> Deserialize<'de> for Property {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    deserializer.deserialize_any(PropertyVisitor)
+  }
+}
+
+// maps a field table (e.g. message headers) onto a struct
+pub fn from_table<T: DeserializeOwned>(table: &PropTable) -> Result<T> {
+  Ok(serde_json::from_value(serde_json::to_value(table)?)?)
+}
+
+pub fn to_table<T: Serialize>(value: &T) -> Result<PropTable> {
+  Ok(serde_json::from_value(serde_json::to_value(value)?)?)
+}
+
+// accepts a missing content type and parameters such as `; charset=utf-8`
+pub(crate) fn is_content_type(content_type: Option<&String>, expected: &str) -> bool {
+  match content_type {
+    Some(content_type) => content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(expected),
+    None => true
+  }
+}
@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use anyhow::bail;
+use crate::Result;
 
 pub type PropTable = HashMap<ShortStr, Property>;
 
@@ -44,6 +46,16 @@ impl From<&str> for LongStr {
   }
 }
 
+// a long string whose content is binary, e.g. SASL responses and challenges
+#[derive(Default, Debug, Clone)]
+pub struct LongBytes(pub Vec<u8>);
+
+impl From<Vec<u8>> for LongBytes {
+  fn from(bytes: Vec<u8>) -> Self {
+    Self(bytes)
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum Property {
   Bool(bool),
@@ -58,5 +70,90 @@ pub enum Property {
   Double(f64),
   ShortStr(ShortStr),
   LongStr(LongStr),
+  Decimal(u8, u32),
+  Timestamp(u64),
+  Array(Vec<Property>),
+  ByteArray(Vec<u8>),
+  Void,
   Table(PropTable)
 }
+
+macro_rules! impl_property_from {
+  ($($ty:ty => $variant:ident),+) => {
+    $(
+      impl From<$ty> for Property {
+        fn from(value: $ty) -> Self {
+          Property::$variant(value)
+        }
+      }
+    )+
+  }
+}
+
+impl_property_from! {
+  bool => Bool,
+  u8 => Byte,
+  i16 => Short,
+  u16 => UShort,
+  i32 => Int,
+  u32 => UInt,
+  i64 => Long,
+  u64 => ULong,
+  f32 => Float,
+  f64 => Double,
+  ShortStr => ShortStr,
+  LongStr => LongStr
+}
+
+impl From<String> for Property {
+  fn from(value: String) -> Self {
+    Property::LongStr(LongStr(value))
+  }
+}
+
+impl From<&str> for Property {
+  fn from(value: &str) -> Self {
+    Property::LongStr(value.into())
+  }
+}
+
+impl<T: Into<Property>> From<Vec<T>> for Property {
+  fn from(value: Vec<T>) -> Self {
+    Property::Array(value.into_iter().map(Into::into).collect())
+  }
+}
+
+impl<K: Into<ShortStr>, V: Into<Property>> From<HashMap<K, V>> for Property {
+  fn from(value: HashMap<K, V>) -> Self {
+    Property::Table(value.into_iter().map(|(key, value)| (key.into(), value.into())).collect())
+  }
+}
+
+// the writer can't report a frame it fails to encode back to whoever queued it, so method fields
+// are checked by the caller; a short string's length prefix is a single byte
+pub(crate) fn check_shortstr(field: &str, value: &str) -> Result<()> {
+  if value.len() > u8::MAX as usize {
+    bail!("{} of {} bytes exceeds 255", field, value.len());
+  }
+
+  Ok(())
+}
+
+// keys and short string values of nested tables and arrays are limited as well
+pub(crate) fn check_table(field: &str, table: &PropTable) -> Result<()> {
+  for (key, value) in table {
+    check_shortstr(field, &key.0)?;
+    check_property(field, value)?;
+  }
+
+  Ok(())
+}
+
+fn check_property(field: &str, value: &Property) -> Result<()> {
+  match value {
+    Property::ShortStr(value) => check_shortstr(field, &value.0),
+    Property::Table(table) => check_table(field, table),
+    Property::Array(values) => values.iter().try_for_each(|value| check_property(field, value)),
+    _ => Ok(())
+  }
+}
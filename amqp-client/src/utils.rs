@@ -1,17 +1,126 @@
-use std::sync::atomic::{AtomicI16, Ordering};
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use crate::api::connection::ConnectionError;
+use crate::protocol::types::ChannelId;
+use crate::Result;
 
+// channel 0 belongs to the connection, so ids start from 1
+const FIRST_ID: ChannelId = 1;
+
+#[derive(Debug)]
+struct IdState {
+  next_id: ChannelId,
+  max_id: ChannelId,
+  released: BTreeSet<ChannelId>,
+}
+
+// hands out the lowest free channel id, ids come back after the channel is closed
+#[derive(Debug)]
 pub struct IdAllocator {
-  prev_id: AtomicI16
+  state: Mutex<IdState>
 }
 
 impl IdAllocator {
-  pub fn new() -> Self {
+  pub fn new(max_id: ChannelId) -> Self {
     Self {
-      prev_id: AtomicI16::new(1)
+      state: Mutex::new(IdState {
+        next_id: FIRST_ID,
+        max_id: Self::effective_max(max_id),
+        released: BTreeSet::new(),
+      })
     }
   }
 
-  pub fn allocate(&mut self) -> i16 {
-    self.prev_id.fetch_add(1, Ordering::Relaxed)
+  // zero means the peers agreed on no limit
+  fn effective_max(max_id: ChannelId) -> ChannelId {
+    if max_id <= 0 { ChannelId::MAX } else { max_id }
+  }
+
+  pub fn set_max(&self, max_id: ChannelId) {
+    self.state.lock().unwrap().max_id = Self::effective_max(max_id);
+  }
+
+  pub fn allocate(&self) -> Result<ChannelId> {
+    let mut state = self.state.lock().unwrap();
+
+    if let Some(id) = state.released.pop_first() {
+      return Ok(id);
+    }
+
+    if state.next_id > state.max_id || state.next_id < FIRST_ID {
+      return Err(ConnectionError::ChannelsExhausted(state.max_id).into());
+    }
+
+    let id = state.next_id;
+    state.next_id = id.checked_add(1).unwrap_or(0);
+    Ok(id)
+  }
+
+  pub fn release(&self, id: ChannelId) {
+    let mut state = self.state.lock().unwrap();
+
+    if id >= FIRST_ID && id < state.next_id {
+      state.released.insert(id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hands_out_ids_from_one() {
+    let allocator = IdAllocator::new(3);
+
+    assert_eq!(allocator.allocate().unwrap(), 1);
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert_eq!(allocator.allocate().unwrap(), 3);
+  }
+
+  #[test]
+  fn fails_once_channel_max_is_reached() {
+    let allocator = IdAllocator::new(1);
+    allocator.allocate().unwrap();
+
+    let err = allocator.allocate().unwrap_err();
+    assert!(matches!(err.downcast_ref::<ConnectionError>(), Some(ConnectionError::ChannelsExhausted(1))));
+  }
+
+  #[test]
+  fn reuses_the_lowest_released_id() {
+    let allocator = IdAllocator::new(3);
+    for _ in 0..3 {
+      allocator.allocate().unwrap();
+    }
+
+    allocator.release(3);
+    allocator.release(2);
+
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert_eq!(allocator.allocate().unwrap(), 3);
+    assert!(allocator.allocate().is_err());
+  }
+
+  #[test]
+  fn ignores_ids_it_never_handed_out() {
+    let allocator = IdAllocator::new(5);
+    allocator.allocate().unwrap();
+
+    allocator.release(0);
+    allocator.release(4);
+
+    assert_eq!(allocator.allocate().unwrap(), 2);
+  }
+
+  #[test]
+  fn treats_zero_and_negative_max_as_no_limit() {
+    for max_id in [0, -1] {
+      let allocator = IdAllocator::new(1);
+      allocator.allocate().unwrap();
+      allocator.set_max(max_id);
+
+      assert_eq!(allocator.allocate().unwrap(), 2);
+    }
   }
 }